@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy_xpbd_3d::prelude::*;
+use interpolation::Lerp;
+
+use crate::car_input::CarInput;
+use crate::car_suspension::CarPhysics;
+use crate::tuning::TuningPreset;
+use crate::{PlayerCar, RaceEntity, RayCastWheelEntity};
+
+/// Every frame of a benchmark simulates exactly one physics step.
+pub const BENCHMARK_TIME_STEP: Duration = Duration::from_nanos(1_000_000_000 / 144);
+
+/// Where the car is put back before every scenario.
+const START_TRANSFORM: Transform = Transform::from_xyz(0.0, 1.6, 0.0);
+
+/// The time given to the car to land on its wheels before a scenario starts.
+const SETTLE_DURATION: f32 = 1.5;
+
+/// Scenarios that didn't finish after this many seconds are considered failed.
+const SCENARIO_TIMEOUT: f32 = 90.0;
+
+const KMH_100: f32 = 100.0 / 3.6;
+
+const SKIDPAD_DURATION: f32 = 30.0;
+const SKIDPAD_MIN_SPEED: f32 = 5.0;
+const SKIDPAD_MAX_SPEED: f32 = 45.0;
+/// The lateral acceleration is averaged over this duration to only keep steady states.
+const SKIDPAD_WINDOW: f32 = 1.0;
+
+const SLALOM_CONES: usize = 6;
+const SLALOM_FIRST_CONE: f32 = 30.0;
+const SLALOM_CONE_SPACING: f32 = 18.0;
+/// How far on the side of the cones the car aims.
+const SLALOM_OFFSET: f32 = 3.0;
+const SLALOM_SPEED: f32 = 60.0 / 3.6;
+/// The car is disqualified when it goes this far from the cones line.
+const SLALOM_MAX_DEVIATION: f32 = 15.0;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Scenario {
+    Acceleration,
+    Braking,
+    Skidpad,
+    Slalom,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 4] =
+        [Scenario::Acceleration, Scenario::Braking, Scenario::Skidpad, Scenario::Slalom];
+
+    fn description(&self) -> &'static str {
+        match self {
+            Scenario::Acceleration => "0-100 km/h acceleration",
+            Scenario::Braking => "100-0 km/h braking distance",
+            Scenario::Skidpad => "skidpad max lateral acceleration",
+            Scenario::Slalom => "slalom time",
+        }
+    }
+}
+
+pub enum ScenarioOutcome {
+    Measured { value: f32, unit: &'static str },
+    Failed(&'static str),
+}
+
+/// Runs the handling scenarios one after the other and prints a report.
+#[derive(Resource)]
+pub struct Benchmark {
+    /// The car definition to apply before running the scenarios.
+    preset: Option<TuningPreset>,
+    pending: VecDeque<Scenario>,
+    current: Option<ScenarioRun>,
+    results: Vec<(Scenario, ScenarioOutcome)>,
+}
+
+impl Benchmark {
+    pub fn new(preset: Option<TuningPreset>) -> Benchmark {
+        Benchmark {
+            preset,
+            pending: Scenario::ALL.into_iter().collect(),
+            current: None,
+            results: Vec::new(),
+        }
+    }
+}
+
+struct ScenarioRun {
+    scenario: Scenario,
+    elapsed: f32,
+    braking_start: Option<Vec3>,
+    next_cone: usize,
+    lateral_accelerations: VecDeque<(f32, f32)>,
+    max_lateral_acceleration: f32,
+}
+
+/// The state of the car the scenarios react to.
+struct CarState {
+    translation: Vec3,
+    forward: Vec3,
+    /// Signed forward speed in m/s.
+    speed: f32,
+    yaw_rate: f32,
+}
+
+impl ScenarioRun {
+    fn new(scenario: Scenario) -> ScenarioRun {
+        ScenarioRun {
+            scenario,
+            elapsed: 0.0,
+            braking_start: None,
+            next_cone: 0,
+            lateral_accelerations: VecDeque::new(),
+            max_lateral_acceleration: 0.0,
+        }
+    }
+
+    /// Returns the input to send to the car and the outcome when the scenario is over.
+    fn step(&mut self, time: f32, car: &CarState) -> (CarInput, Option<ScenarioOutcome>) {
+        let mut input = CarInput::default();
+
+        let outcome = match self.scenario {
+            Scenario::Acceleration => {
+                input.accelerate = true;
+                (car.speed >= KMH_100)
+                    .then_some(ScenarioOutcome::Measured { value: time, unit: "s" })
+            }
+            Scenario::Braking => match self.braking_start {
+                None => {
+                    input.accelerate = true;
+                    if car.speed >= KMH_100 {
+                        self.braking_start = Some(car.translation);
+                    }
+                    None
+                }
+                Some(start) => {
+                    input.reverse = true;
+                    (car.speed <= 0.0).then(|| {
+                        let distance = start.xz().distance(car.translation.xz());
+                        ScenarioOutcome::Measured { value: distance, unit: "m" }
+                    })
+                }
+            },
+            Scenario::Skidpad => {
+                let progress = time / SKIDPAD_DURATION;
+                let target_speed = SKIDPAD_MIN_SPEED.lerp(&SKIDPAD_MAX_SPEED, &progress);
+                input.steer_right = true;
+                input.accelerate = car.speed < target_speed;
+
+                // a = v²/r = v * ω
+                let lateral_acceleration = (car.speed * car.yaw_rate).abs();
+                self.lateral_accelerations.push_back((time, lateral_acceleration));
+                while self
+                    .lateral_accelerations
+                    .front()
+                    .map_or(false, |(t, _)| time - t > SKIDPAD_WINDOW)
+                {
+                    self.lateral_accelerations.pop_front();
+                }
+
+                if time >= SKIDPAD_WINDOW {
+                    let sum: f32 = self.lateral_accelerations.iter().map(|(_, a)| a).sum();
+                    let average = sum / self.lateral_accelerations.len() as f32;
+                    self.max_lateral_acceleration = self.max_lateral_acceleration.max(average);
+                }
+
+                (progress >= 1.0).then(|| ScenarioOutcome::Measured {
+                    value: self.max_lateral_acceleration / 9.81,
+                    unit: "g",
+                })
+            }
+            Scenario::Slalom => {
+                let cone_z = |i: usize| -(SLALOM_FIRST_CONE + i as f32 * SLALOM_CONE_SPACING);
+                if car.translation.z < cone_z(self.next_cone) {
+                    self.next_cone += 1;
+                }
+
+                if self.next_cone == SLALOM_CONES {
+                    Some(ScenarioOutcome::Measured { value: time, unit: "s" })
+                } else if car.translation.x.abs() > SLALOM_MAX_DEVIATION {
+                    Some(ScenarioOutcome::Failed("missed a cone"))
+                } else {
+                    let side = if self.next_cone % 2 == 0 { -1.0 } else { 1.0 };
+                    let target = Vec3::new(side * SLALOM_OFFSET, 0.0, cone_z(self.next_cone));
+                    let mut to_target = target - car.translation;
+                    to_target.y = 0.0;
+                    // Positive when the target is on the left of the car
+                    let side_of_target = car.forward.cross(to_target.normalize_or_zero()).y;
+
+                    input.accelerate = car.speed < SLALOM_SPEED;
+                    input.steer_left = side_of_target > 0.03;
+                    input.steer_right = side_of_target < -0.03;
+                    None
+                }
+            }
+        };
+
+        (input, outcome)
+    }
+}
+
+pub fn spawn_benchmark_ground(mut commands: Commands) {
+    commands.spawn((
+        RaceEntity,
+        RigidBody::Static,
+        TransformBundle::from(Transform::from_xyz(0.0, -0.5, 0.0)),
+        Collider::cuboid(4000.0, 1.0, 4000.0),
+    ));
+}
+
+/// Applies the benchmarked car definition as soon as the car is spawned.
+pub fn apply_benchmark_preset(world: &mut World) {
+    let Ok(car) = world.query_filtered::<Entity, With<PlayerCar>>().get_single(world) else {
+        return;
+    };
+    let Some(preset) = world.resource_mut::<Benchmark>().preset.take() else { return };
+    if let Err(e) = preset.apply(world, car) {
+        error!("could not apply the car definition: {e}");
+    }
+}
+
+pub fn drive_benchmark(
+    time: Res<Time>,
+    mut benchmark: ResMut<Benchmark>,
+    mut app_exit: EventWriter<AppExit>,
+    mut car_query: Query<
+        (
+            &mut CarInput,
+            &mut CarPhysics,
+            &mut Transform,
+            &mut Position,
+            &mut Rotation,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+        ),
+        With<PlayerCar>,
+    >,
+    raycast_query: Query<(), With<RayCastWheelEntity>>,
+) {
+    let Ok((
+        mut car_input,
+        mut car_physics,
+        mut car_transform,
+        mut position,
+        mut rotation,
+        mut lin_vel,
+        mut ang_vel,
+    )) = car_query.get_single_mut()
+    else {
+        return;
+    };
+
+    // Wait for the wheels to be identified in the car scene
+    if raycast_query.iter().count() < 4 {
+        return;
+    }
+
+    let Some(run) = benchmark.current.as_mut() else {
+        match benchmark.pending.pop_front() {
+            Some(scenario) => {
+                *car_transform = START_TRANSFORM;
+                position.0 = START_TRANSFORM.translation;
+                rotation.0 = START_TRANSFORM.rotation;
+                lin_vel.0 = Vec3::ZERO;
+                ang_vel.0 = Vec3::ZERO;
+                car_physics.wheel_rotation = 0.5;
+                *car_input = CarInput::default();
+                benchmark.current = Some(ScenarioRun::new(scenario));
+            }
+            None => {
+                print_report(&benchmark.results);
+                app_exit.send(AppExit);
+            }
+        }
+        return;
+    };
+
+    run.elapsed += time.delta_seconds();
+    if run.elapsed < SETTLE_DURATION {
+        return;
+    }
+
+    let car = CarState {
+        translation: car_transform.translation,
+        forward: car_transform.forward(),
+        speed: car_transform.forward().dot(lin_vel.0),
+        yaw_rate: ang_vel.0.y,
+    };
+
+    let scenario_time = run.elapsed - SETTLE_DURATION;
+    let (input, outcome) = run.step(scenario_time, &car);
+    car_input.set_if_neq(input);
+
+    let outcome = outcome.or_else(|| {
+        (scenario_time > SCENARIO_TIMEOUT).then_some(ScenarioOutcome::Failed("timed out"))
+    });
+
+    if let Some(outcome) = outcome {
+        let scenario = run.scenario;
+        benchmark.results.push((scenario, outcome));
+        benchmark.current = None;
+    }
+}
+
+fn print_report(results: &[(Scenario, ScenarioOutcome)]) {
+    println!("{:<36} result", "scenario");
+    for (scenario, outcome) in results {
+        match outcome {
+            ScenarioOutcome::Measured { value, unit } => {
+                println!("{:<36} {value:.2} {unit}", scenario.description())
+            }
+            ScenarioOutcome::Failed(reason) => {
+                println!("{:<36} failed: {reason}", scenario.description())
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use bevy::input::mouse::MouseMotion;
+use bevy::prelude::*;
+use bevy_dolly::dolly::drivers::{Arm, LookAt, Position, Rotation, Smooth, YawPitch};
+use bevy_dolly::dolly_type::Rig;
+
+use crate::{MainCamera, PlayerCar};
+
+/// The duration of the blend between the previous camera and the new one.
+const TRANSITION_DURATION: f32 = 0.6;
+
+/// The trackside camera moves ahead of the car when it gets further than that.
+const TRACKSIDE_MAX_DISTANCE: f32 = 80.0;
+
+const FREE_FLY_SPEED: f32 = 20.0;
+const FREE_FLY_MOUSE_SENSITIVITY: f32 = 0.15;
+
+#[derive(Resource, Reflect, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[reflect(Resource)]
+pub enum CameraMode {
+    #[default]
+    Chase,
+    Hood,
+    Bumper,
+    Cockpit,
+    Trackside,
+    FreeFly,
+}
+
+impl CameraMode {
+    pub fn next(self) -> CameraMode {
+        match self {
+            CameraMode::Chase => CameraMode::Hood,
+            CameraMode::Hood => CameraMode::Bumper,
+            CameraMode::Bumper => CameraMode::Cockpit,
+            CameraMode::Cockpit => CameraMode::Trackside,
+            CameraMode::Trackside => CameraMode::FreeFly,
+            CameraMode::FreeFly => CameraMode::Chase,
+        }
+    }
+
+    /// Builds the rig of this mode from the current car and camera transforms.
+    pub fn rig(self, car_transform: &Transform, camera_transform: &Transform) -> Rig {
+        match self {
+            // Look at and follow vehicule
+            CameraMode::Chase => Rig::builder()
+                .with(Position::new(car_transform.translation))
+                .with(Rotation::new(car_transform.rotation))
+                .with(Smooth::new_position(2.5))
+                .with(Arm::new(Vec3::new(0.0, 2.5, 8.0)))
+                .with(
+                    LookAt::new(car_transform.translation + Vec3::Y)
+                        .tracking_smoothness(2.0)
+                        .tracking_predictive(true),
+                )
+                .build(),
+            CameraMode::Hood => mounted_rig(car_transform, Vec3::new(0.0, 0.75, -1.0)),
+            CameraMode::Bumper => mounted_rig(car_transform, Vec3::new(0.0, 0.0, -2.3)),
+            CameraMode::Cockpit => mounted_rig(car_transform, Vec3::new(-0.35, 0.55, 0.3)),
+            // Look at vehicule from a fixed point
+            CameraMode::Trackside => Rig::builder()
+                .with(Position::new(trackside_position(car_transform)))
+                .with(Rotation::new(Quat::IDENTITY))
+                .with(
+                    LookAt::new(car_transform.translation + Vec3::Y)
+                        .tracking_smoothness(1.25)
+                        .tracking_predictive(true),
+                )
+                .build(),
+            CameraMode::FreeFly => Rig::builder()
+                .with(Position::new(camera_transform.translation))
+                .with(YawPitch::new().rotation_quat(camera_transform.rotation))
+                .with(Smooth::new_position_rotation(1.0, 0.5))
+                .build(),
+        }
+    }
+}
+
+/// A camera rigidly attached to the car at the given offset, looking forward.
+fn mounted_rig(car_transform: &Transform, offset: Vec3) -> Rig {
+    Rig::builder()
+        .with(Position::new(car_transform.translation))
+        .with(Rotation::new(car_transform.rotation))
+        .with(Smooth::new_rotation(0.1))
+        .with(Arm::new(offset))
+        .build()
+}
+
+/// A point on the side of the road ahead of the car.
+fn trackside_position(car_transform: &Transform) -> Vec3 {
+    car_transform.translation
+        + car_transform.forward() * 40.0
+        + car_transform.right() * 8.0
+        + Vec3::Y * 3.0
+}
+
+/// Blends the camera from where it was when the mode changed.
+#[derive(Component)]
+pub struct CameraTransition {
+    from: Transform,
+    elapsed: f32,
+}
+
+impl CameraTransition {
+    pub fn new(from: Transform) -> CameraTransition {
+        CameraTransition { from, elapsed: 0.0 }
+    }
+
+    pub fn shift(&mut self, offset: Vec3) {
+        self.from.translation += offset;
+    }
+}
+
+/// Rebuilds the rig after the world moved by the offset, seeded with where the camera was
+/// so that its smoothing carries on instead of snapping to the car.
+pub fn shift_rig(
+    mode: CameraMode,
+    rig: &mut Rig,
+    offset: Vec3,
+    car_transform: &Transform,
+    camera_transform: &Transform,
+) {
+    let old_position = rig.try_driver::<Position>().map(|driver| driver.position);
+    let old_target = rig.try_driver::<LookAt>().map(|driver| driver.target);
+    let rotation = rig.try_driver::<Rotation>().map_or(Quat::IDENTITY, |driver| driver.rotation);
+    let arm = rig.try_driver::<Arm>().map_or(Vec3::ZERO, |driver| driver.offset);
+
+    let mut shifted = mode.rig(car_transform, camera_transform);
+
+    // The smoothed position and target the camera had, the first update doesn't smooth
+    if let Some(driver) = shifted.try_driver_mut::<Position>() {
+        driver.position = camera_transform.translation - rotation * arm;
+    }
+    if let (Some(driver), Some(target)) = (shifted.try_driver_mut::<LookAt>(), old_target) {
+        let distance = camera_transform.translation.distance(target + offset);
+        driver.target = camera_transform.translation + camera_transform.forward() * distance;
+    }
+    shifted.update(0.0);
+
+    // Then what the rig was following
+    if let (Some(driver), Some(position)) = (shifted.try_driver_mut::<Position>(), old_position) {
+        driver.position = position + offset;
+    }
+    if let (Some(driver), Some(target)) = (shifted.try_driver_mut::<LookAt>(), old_target) {
+        driver.target = target + offset;
+    }
+    *rig = shifted;
+}
+
+pub fn switch_camera_mode(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut mode: ResMut<CameraMode>,
+    camera_q: Query<(Entity, &Transform), With<MainCamera>>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    if !keys.just_pressed(KeyCode::C) {
+        return;
+    }
+
+    let Ok((camera, &camera_transform)) = camera_q.get_single() else { return };
+    let Ok(car_transform) = car_q.get_single() else { return };
+
+    *mode = mode.next();
+    commands.entity(camera).insert((
+        mode.rig(car_transform, &camera_transform),
+        CameraTransition::new(camera_transform),
+    ));
+}
+
+pub fn update_camera(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mode: Res<CameraMode>,
+    mut rig_q: Query<&mut Rig>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    let mouse_delta: Vec2 = mouse_motion.read().map(|motion| motion.delta).sum();
+
+    let mut rig = rig_q.single_mut();
+    let transform = car_q.single();
+
+    match *mode {
+        CameraMode::Chase | CameraMode::Hood | CameraMode::Bumper | CameraMode::Cockpit => {
+            if let Some(position) = rig.try_driver_mut::<Position>() {
+                position.position = transform.translation;
+            }
+            if let Some(rotation) = rig.try_driver_mut::<Rotation>() {
+                rotation.rotation = transform.rotation;
+            }
+            if let Some(look_at) = rig.try_driver_mut::<LookAt>() {
+                look_at.target = transform.translation + Vec3::Y;
+            }
+        }
+        CameraMode::Trackside => {
+            if let Some(position) = rig.try_driver_mut::<Position>() {
+                if position.position.distance(transform.translation) > TRACKSIDE_MAX_DISTANCE {
+                    position.position = trackside_position(transform);
+                }
+            }
+            if let Some(look_at) = rig.try_driver_mut::<LookAt>() {
+                look_at.target = transform.translation + Vec3::Y;
+            }
+        }
+        CameraMode::FreeFly => {
+            let Some(yaw_pitch) = rig.try_driver_mut::<YawPitch>() else { return };
+            if mouse_buttons.pressed(MouseButton::Right) {
+                yaw_pitch.rotate_yaw_pitch(
+                    -mouse_delta.x * FREE_FLY_MOUSE_SENSITIVITY,
+                    -mouse_delta.y * FREE_FLY_MOUSE_SENSITIVITY,
+                );
+            }
+            let rotation =
+                Quat::from_euler(EulerRot::YXZ, yaw_pitch.yaw_degrees.to_radians(), 0.0, 0.0);
+
+            let mut direction = Vec3::ZERO;
+            for (key, dir) in [
+                (KeyCode::W, Vec3::NEG_Z),
+                (KeyCode::S, Vec3::Z),
+                (KeyCode::A, Vec3::NEG_X),
+                (KeyCode::D, Vec3::X),
+                (KeyCode::E, Vec3::Y),
+                (KeyCode::Q, Vec3::NEG_Y),
+            ] {
+                if keys.pressed(key) {
+                    direction += dir;
+                }
+            }
+
+            let speed = if keys.pressed(KeyCode::ShiftLeft) { 4.0 } else { 1.0 } * FREE_FLY_SPEED;
+            let translation = rotation * direction.normalize_or_zero() * speed;
+            if let Some(position) = rig.try_driver_mut::<Position>() {
+                position.translate(translation * time.delta_seconds());
+            }
+        }
+    }
+}
+
+pub fn blend_camera_transition(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut camera_q: Query<(Entity, &mut Transform, &mut CameraTransition), With<MainCamera>>,
+) {
+    for (entity, mut transform, mut transition) in &mut camera_q {
+        transition.elapsed += time.delta_seconds();
+        let t = (transition.elapsed / TRANSITION_DURATION).min(1.0);
+        // Ease in and out
+        let t = t * t * (3.0 - 2.0 * t);
+
+        let from = transition.from;
+        transform.translation = from.translation.lerp(transform.translation, t);
+        transform.rotation = from.rotation.slerp(transform.rotation, t);
+
+        if transition.elapsed >= TRANSITION_DURATION {
+            commands.entity(entity).remove::<CameraTransition>();
+        }
+    }
+}
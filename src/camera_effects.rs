@@ -0,0 +1,183 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use interpolation::Lerp;
+
+use crate::camera::CameraMode;
+use crate::car_suspension::CarPhysics;
+use crate::telemetry::WheelTelemetry;
+use crate::{MainCamera, PlayerCar};
+
+/// How the camera reacts to the car, tweakable from the inspector.
+#[derive(Reflect, Debug, Clone, Copy)]
+pub struct CameraEffects {
+    /// The vertical field of view when the car is stopped, in degrees.
+    pub base_fov: f32,
+    /// The vertical field of view reached at `max_fov_speed`, in degrees.
+    pub max_fov: f32,
+    /// The speed, in km/h, at which the field of view is the widest.
+    pub max_fov_speed: f32,
+    /// The amplitude of the shake, in meters, for a fully compressed suspension.
+    pub compression_shake: f32,
+    /// The amplitude of the shake, in meters, per m/s of suspension movement.
+    pub roughness_shake: f32,
+    /// How much the camera turns into the corner, in radians per rad/s of yaw rate.
+    pub yaw_look_ahead: f32,
+    /// How much the camera turns into the corner, in radians for a full steering lock.
+    pub steering_look_ahead: f32,
+    /// How fast the effects follow the car, higher is snappier.
+    pub responsiveness: f32,
+}
+
+impl CameraEffects {
+    const NONE: CameraEffects = CameraEffects {
+        base_fov: 45.0,
+        max_fov: 45.0,
+        max_fov_speed: 1.0,
+        compression_shake: 0.0,
+        roughness_shake: 0.0,
+        yaw_look_ahead: 0.0,
+        steering_look_ahead: 0.0,
+        responsiveness: 5.0,
+    };
+}
+
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource)]
+pub struct CameraEffectsSettings {
+    pub chase: CameraEffects,
+    pub hood: CameraEffects,
+    pub bumper: CameraEffects,
+    pub cockpit: CameraEffects,
+    pub trackside: CameraEffects,
+    pub free_fly: CameraEffects,
+}
+
+impl CameraEffectsSettings {
+    pub fn for_mode(&self, mode: CameraMode) -> &CameraEffects {
+        match mode {
+            CameraMode::Chase => &self.chase,
+            CameraMode::Hood => &self.hood,
+            CameraMode::Bumper => &self.bumper,
+            CameraMode::Cockpit => &self.cockpit,
+            CameraMode::Trackside => &self.trackside,
+            CameraMode::FreeFly => &self.free_fly,
+        }
+    }
+}
+
+impl Default for CameraEffectsSettings {
+    fn default() -> CameraEffectsSettings {
+        let chase = CameraEffects {
+            base_fov: 45.0,
+            max_fov: 65.0,
+            max_fov_speed: 250.0,
+            compression_shake: 0.01,
+            roughness_shake: 0.02,
+            yaw_look_ahead: 0.15,
+            steering_look_ahead: 0.1,
+            responsiveness: 4.0,
+        };
+        let hood = CameraEffects {
+            base_fov: 55.0,
+            max_fov: 75.0,
+            compression_shake: 0.015,
+            roughness_shake: 0.03,
+            yaw_look_ahead: 0.1,
+            steering_look_ahead: 0.05,
+            ..chase
+        };
+        CameraEffectsSettings {
+            chase,
+            hood,
+            bumper: CameraEffects { compression_shake: 0.02, roughness_shake: 0.04, ..hood },
+            cockpit: CameraEffects { base_fov: 60.0, max_fov: 70.0, ..hood },
+            trackside: CameraEffects { base_fov: 35.0, max_fov: 35.0, ..CameraEffects::NONE },
+            free_fly: CameraEffects::NONE,
+        }
+    }
+}
+
+/// The smoothed values the effects are computed from.
+#[derive(Component, Default)]
+pub struct CameraEffectsState {
+    fov: Option<f32>,
+    look_ahead: f32,
+    shake_intensity: f32,
+    previous_compressions: Vec<f32>,
+}
+
+pub fn apply_camera_effects(
+    time: Res<Time>,
+    mode: Res<CameraMode>,
+    settings: Res<CameraEffectsSettings>,
+    mut camera_q: Query<
+        (&mut Transform, &mut Projection, &mut CameraEffectsState),
+        (With<MainCamera>, Without<PlayerCar>),
+    >,
+    car_q: Query<
+        (Entity, &CarPhysics, &LinearVelocity, &AngularVelocity, &Transform),
+        With<PlayerCar>,
+    >,
+    wheels_q: Query<(&Parent, &WheelTelemetry)>,
+) {
+    let Ok((mut transform, mut projection, mut state)) = camera_q.get_single_mut() else { return };
+    let Ok((car, car_physics, &LinearVelocity(lin_vel), &AngularVelocity(ang_vel), car_transform)) =
+        car_q.get_single()
+    else {
+        return;
+    };
+
+    let effects = settings.for_mode(*mode);
+    let dt = time.delta_seconds();
+    if dt <= 0.0 {
+        return;
+    }
+    let smoothing = (effects.responsiveness * dt).min(1.0);
+
+    // Widen the field of view with the speed
+    let speed_kmh = car_transform.forward().dot(lin_vel).abs() * 3.6;
+    let fov_progress = (speed_kmh / effects.max_fov_speed).clamp(0.0, 1.0);
+    let target_fov = effects.base_fov.lerp(&effects.max_fov, &fov_progress).to_radians();
+    let fov = state.fov.map_or(target_fov, |fov| fov.lerp(&target_fov, &smoothing));
+    state.fov = Some(fov);
+    if let Projection::Perspective(perspective) = projection.as_mut() {
+        perspective.fov = fov;
+    }
+
+    // Turn into the corners, the steering is centered at 0.5
+    let steering = (car_physics.wheel_rotation - 0.5) / 0.3;
+    let target_look_ahead =
+        -ang_vel.y * effects.yaw_look_ahead - steering * effects.steering_look_ahead;
+    state.look_ahead = state.look_ahead.lerp(&target_look_ahead, &smoothing);
+    transform.rotate_y(state.look_ahead);
+
+    // Shake with the compression of the suspensions and how fast they move
+    let compressions: Vec<_> = wheels_q
+        .iter()
+        .filter(|(parent, _)| parent.get() == car)
+        .map(|(_, wheel)| wheel.compression)
+        .collect();
+    let wheels = compressions.len().max(1) as f32;
+    let compression = compressions.iter().sum::<f32>() / wheels;
+    let roughness = if state.previous_compressions.len() == compressions.len() {
+        let movement: f32 =
+            compressions.iter().zip(&state.previous_compressions).map(|(c, p)| (c - p).abs()).sum();
+        movement / wheels / dt
+    } else {
+        0.0
+    };
+    state.previous_compressions = compressions;
+
+    let target_intensity = compression * effects.compression_shake
+        + roughness * car_physics.max_suspension * effects.roughness_shake;
+    state.shake_intensity = state.shake_intensity.lerp(&target_intensity, &smoothing);
+
+    let t = time.elapsed_seconds();
+    let noise = Vec3::new(
+        (t * 37.0).sin() + (t * 23.0).sin() * 0.5,
+        (t * 41.0).sin() + (t * 29.0).cos() * 0.5,
+        0.0,
+    );
+    let shake = transform.rotation * noise * state.shake_intensity;
+    transform.translation += shake;
+}
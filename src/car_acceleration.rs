@@ -2,43 +2,64 @@ use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::*;
 use interpolation::Lerp;
 
+use crate::car_damage::CarDamage;
+use crate::car_input::CarInput;
 use crate::car_suspension::CarPhysics;
+use crate::telemetry::WheelTelemetry;
 use crate::{CarWheel, RayCastWheelEntity};
 
 pub fn car_acceleration(
-    keys: Res<Input<KeyCode>>,
     mut car_query: Query<
-        (&CarPhysics, &LinearVelocity, &mut ExternalForce, &Transform, &CenterOfMass),
+        (
+            &CarPhysics,
+            &CarDamage,
+            &CarInput,
+            &LinearVelocity,
+            &mut ExternalForce,
+            &Transform,
+            &CenterOfMass,
+        ),
         Without<CarWheel>,
     >,
     wheels_transforms_query: Query<(&CarWheel, &Transform), Without<CarPhysics>>,
-    raycast_query: Query<(&RayCastWheelEntity, &RayCaster, &RayHits)>,
+    mut raycast_query: Query<(
+        &Parent,
+        &RayCastWheelEntity,
+        &RayCaster,
+        &RayHits,
+        &mut WheelTelemetry,
+    )>,
 ) {
-    let Ok((
-        car_physics,
-        &LinearVelocity(lin_vel),
-        mut external_force,
-        &car_transform,
-        &CenterOfMass(car_center_of_mass),
-    )) = car_query.get_single_mut()
-    else {
-        return;
-    };
+    for (car, &RayCastWheelEntity(entity), ray, hits, mut telemetry) in &mut raycast_query {
+        let Ok((
+            car_physics,
+            damage,
+            car_input,
+            &LinearVelocity(lin_vel),
+            mut external_force,
+            &car_transform,
+            &CenterOfMass(car_center_of_mass),
+        )) = car_query.get_mut(car.get())
+        else {
+            continue;
+        };
 
-    let CarPhysics { top_speed, .. } = *car_physics;
+        let CarPhysics { top_speed, .. } = *car_physics;
 
-    let accel_input = if keys.pressed(KeyCode::Up) || keys.pressed(KeyCode::Down) {
-        top_speed
-    } else {
-        top_speed / 10.0
-    };
+        // A damaged engine gives less torque, coasting only uses the brakes
+        let accel_input = if car_input.accelerate || car_input.reverse {
+            top_speed * damage.engine_power()
+        } else {
+            top_speed / 10.0
+        };
 
-    for (&RayCastWheelEntity(entity), ray, hits) in &raycast_query {
         let (car_wheel, &wheel_transform) = wheels_transforms_query.get(entity).unwrap();
 
         assert!(hits.len() <= 1);
         let hit = hits.as_slice().get(0);
 
+        telemetry.accel_force = 0.0;
+
         // acceleration / braking
         if hit.is_some() && matches!(car_wheel, CarWheel::BackRight | CarWheel::BackLeft) {
             // Forward speed of the car (in the direction of driving)
@@ -46,9 +67,9 @@ pub fn car_acceleration(
 
             // World-space direction of the acceleration/braking force.
             #[allow(clippy::collapsible_else_if)]
-            let accel_dir = if keys.pressed(KeyCode::Up) {
+            let accel_dir = if car_input.accelerate {
                 (car_transform * wheel_transform).forward()
-            } else if keys.pressed(KeyCode::Down) {
+            } else if car_input.reverse {
                 (car_transform * wheel_transform).back()
             } else {
                 if car_speed > 0.0 {
@@ -67,6 +88,9 @@ pub fn car_acceleration(
                 // Available torque
                 let available_torque = evaluate_power_curve(normalized_speed) * accel_input;
 
+                // Signed along the car, positive when pushing it forward
+                telemetry.accel_force = accel_dir.dot(car_transform.forward()) * available_torque;
+
                 external_force.persistent = false;
                 external_force.apply_force_at_point(
                     accel_dir * available_torque,
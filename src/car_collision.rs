@@ -0,0 +1,63 @@
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::car_suspension::CarPhysics;
+
+/// The body of a car hit something, the wheels only touch the ground with their rays.
+///
+/// A collision between two cars is sent once for each of them.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct CarCollision {
+    pub car: Entity,
+    pub other: Entity,
+    /// How much the impact changed the velocity of the two bodies relative to each other,
+    /// in m/s, the glancing blows barely count.
+    pub impact_speed: f32,
+    /// How much the impact changed the velocity of the car alone, in m/s, little when it
+    /// hits something much lighter.
+    pub velocity_change: f32,
+    /// Where the car was hit from, its center, in world space.
+    pub direction: Vec3,
+}
+
+/// The velocity of a body before the last physics step.
+#[derive(Component, Debug, Default, Clone, Copy)]
+pub struct PreviousVelocity(pub Vec3);
+
+/// Turns the collisions started by the car colliders into `CarCollision` events.
+pub fn detect_car_collisions(
+    mut collisions: EventReader<CollisionStarted>,
+    mut car_collisions: EventWriter<CarCollision>,
+    cars_q: Query<(), With<CarPhysics>>,
+    bodies_q: Query<(&PreviousVelocity, &LinearVelocity)>,
+) {
+    // The bodies without a previous velocity, like the static ones, didn't move
+    let velocity_change = |entity: Entity| {
+        bodies_q.get(entity).map_or(Vec3::ZERO, |(previous, current)| current.0 - previous.0)
+    };
+
+    for &CollisionStarted(a, b) in collisions.read() {
+        for (car, other) in [(a, b), (b, a)] {
+            if !cars_q.contains(car) {
+                continue;
+            }
+            // The car is pushed away from where it was hit
+            let change = velocity_change(car) - velocity_change(other);
+            car_collisions.send(CarCollision {
+                car,
+                other,
+                impact_speed: change.length(),
+                velocity_change: velocity_change(car).length(),
+                direction: -change.normalize_or_zero(),
+            });
+        }
+    }
+}
+
+/// Keeps the velocities from before the next physics step, once the collisions of the
+/// last one are read.
+pub fn record_previous_velocities(mut bodies_q: Query<(&mut PreviousVelocity, &LinearVelocity)>) {
+    for (mut previous, velocity) in &mut bodies_q {
+        previous.0 = velocity.0;
+    }
+}
@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+use bevy::render::mesh::VertexAttributeValues;
+
+use crate::car_collision::CarCollision;
+use crate::car_input::CarInput;
+use crate::car_suspension::CarPhysics;
+use crate::CarWheel;
+
+/// The impacts changing the velocity of the car by less than this, in m/s, leave no mark.
+const DAMAGE_THRESHOLD: f32 = 3.0;
+
+/// The damage of an impact for every m/s above the threshold.
+const DAMAGE_PER_SPEED: f32 = 0.04;
+
+/// How far from a wheel, in meters, an impact still bends it.
+const WHEEL_REACH: f32 = 0.8;
+
+/// How far the wheels of a wrecked front axle turn from straight, in wheel rotation.
+const MAX_MISALIGNMENT: f32 = 0.08;
+
+/// How deep, in meters, the dent of an impact doing full damage is, and how wide.
+const MAX_DENT_DEPTH: f32 = 0.25;
+const DENT_RADIUS: f32 = 0.7;
+
+/// How much every part of a car is damaged, from 0 when intact to 1 when wrecked.
+///
+/// The vehicle systems scale the tuned `CarPhysics` with it, repairing the car only
+/// resets it.
+#[derive(Component, Reflect, Debug, Default, Clone)]
+#[reflect(Component)]
+pub struct CarDamage {
+    pub front: f32,
+    pub rear: f32,
+    pub left: f32,
+    pub right: f32,
+    /// Indexed by `CarWheel`.
+    pub wheels: [f32; 4],
+}
+
+impl CarDamage {
+    /// The share of the engine torque left, the engine of the Porsche is at the back.
+    pub fn engine_power(&self) -> f32 {
+        1.0 - 0.6 * self.rear - 0.2 * self.front
+    }
+
+    /// The wheel rotation the steering goes back to, off straight when a front wheel
+    /// is bent, pulling toward its side.
+    pub fn straight_wheel_rotation(&self) -> f32 {
+        let pull = self.wheel(CarWheel::FrontRight) - self.wheel(CarWheel::FrontLeft);
+        0.5 + pull * MAX_MISALIGNMENT
+    }
+
+    /// The share of the tire grip left.
+    pub fn grip(&self, wheel: CarWheel) -> f32 {
+        1.0 - 0.5 * self.wheel(wheel)
+    }
+
+    /// The share of the suspension strength left.
+    pub fn suspension(&self, wheel: CarWheel) -> f32 {
+        1.0 - 0.4 * self.wheel(wheel)
+    }
+
+    pub fn wheel(&self, wheel: CarWheel) -> f32 {
+        self.wheels[wheel as usize]
+    }
+
+    /// The damage of the most damaged part.
+    pub fn worst(&self) -> f32 {
+        [self.front, self.rear, self.left, self.right]
+            .into_iter()
+            .chain(self.wheels)
+            .fold(0.0, f32::max)
+    }
+
+    /// Damages the part of the body hit from a direction relative to the car, and the
+    /// wheels near where it was hit.
+    fn hit(&mut self, car_physics: &CarPhysics, direction: Vec3, amount: f32) {
+        let chassis = car_physics.chassis_size;
+        let wheels = [
+            (CarWheel::FrontRight, Vec3::new(chassis.x, 0.0, -chassis.z)),
+            (CarWheel::FrontLeft, Vec3::new(-chassis.x, 0.0, -chassis.z)),
+            (CarWheel::BackRight, Vec3::new(chassis.x, 0.0, chassis.z)),
+            (CarWheel::BackLeft, Vec3::new(-chassis.x, 0.0, chassis.z)),
+        ];
+
+        // Landing on its belly shakes all the wheels
+        if direction.y < -0.7 {
+            for (wheel, _) in wheels {
+                let damage = &mut self.wheels[wheel as usize];
+                *damage = (*damage + amount / 2.0).min(1.0);
+            }
+            return;
+        }
+
+        // Where the direction leaves the body, seen from above
+        let half_size = Vec2::new(1.0, 2.2);
+        let flat = direction.xz();
+        let scale = (flat.abs() / half_size).max_element();
+        if scale <= f32::EPSILON {
+            return;
+        }
+        let point = flat / scale;
+        let zone = if (flat.x / half_size.x).abs() > (flat.y / half_size.y).abs() {
+            if flat.x < 0.0 {
+                &mut self.left
+            } else {
+                &mut self.right
+            }
+        } else if flat.y < 0.0 {
+            &mut self.front
+        } else {
+            &mut self.rear
+        };
+        *zone = (*zone + amount).min(1.0);
+
+        for (wheel, position) in wheels {
+            if position.xz().distance(point) < WHEEL_REACH {
+                let damage = &mut self.wheels[wheel as usize];
+                *damage = (*damage + amount).min(1.0);
+            }
+        }
+    }
+}
+
+/// A mesh of a car dented by the impacts, a copy of the one it shared with the other cars.
+#[derive(Component)]
+pub struct Dented {
+    original: Handle<Mesh>,
+}
+
+/// Damages the cars with their impacts and dents their body where they were hit.
+pub fn damage_cars(
+    mut collisions: EventReader<CarCollision>,
+    mut cars_q: Query<(&mut CarDamage, &CarPhysics, &Transform)>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    children_q: Query<&Children>,
+    mut meshes_q: Query<(&mut Handle<Mesh>, &GlobalTransform, Option<&Dented>)>,
+) {
+    // The copies made by this run, their `Dented` is only inserted afterward
+    let mut copied = Vec::new();
+    for collision in collisions.read() {
+        let amount = (collision.velocity_change - DAMAGE_THRESHOLD) * DAMAGE_PER_SPEED;
+        if amount <= 0.0 {
+            continue;
+        }
+        let Ok((mut damage, car_physics, car_transform)) = cars_q.get_mut(collision.car) else {
+            continue;
+        };
+        let local = car_transform.rotation.inverse() * collision.direction;
+        damage.hit(car_physics, local, amount);
+
+        // Push the body in around where it was hit, the chassis collider being 2 by 4.4
+        let scale = (local.abs() / Vec3::new(1.0, 0.5, 2.2)).max_element();
+        if scale <= f32::EPSILON {
+            continue;
+        }
+        let impact = car_transform.transform_point(local / scale);
+        let depth = amount.min(1.0) * MAX_DENT_DEPTH;
+
+        for entity in children_q.iter_descendants(collision.car) {
+            let Ok((mut handle, global_transform, dented)) = meshes_q.get_mut(entity) else {
+                continue;
+            };
+            // Deform a copy, the meshes of the scene are shared by all the cars
+            if dented.is_none() && !copied.contains(&entity) {
+                let Some(copy) = meshes.get(handle.id()).cloned() else { continue };
+                commands.entity(entity).insert(Dented { original: handle.clone() });
+                *handle = meshes.add(copy);
+                copied.push(entity);
+            }
+            let Some(mesh) = meshes.get_mut(handle.id()) else { continue };
+            dent_mesh(mesh, global_transform, impact, collision.direction * -depth);
+        }
+    }
+}
+
+/// Moves the vertices near the impact point, less the further they are.
+fn dent_mesh(mesh: &mut Mesh, transform: &GlobalTransform, impact: Vec3, push: Vec3) {
+    let Some(VertexAttributeValues::Float32x3(positions)) =
+        mesh.attribute_mut(Mesh::ATTRIBUTE_POSITION)
+    else {
+        return;
+    };
+    let inverse = transform.affine().inverse();
+    for position in positions {
+        let world = transform.transform_point(Vec3::from(*position));
+        let falloff = 1.0 - world.distance(impact) / DENT_RADIUS;
+        if falloff > 0.0 {
+            *position = inverse.transform_point3(world + push * falloff).to_array();
+        }
+    }
+}
+
+/// Repairs the cars asking for it, their body included.
+///
+/// The repair is one of the inputs, so that the replays repair the car on the same frame.
+pub fn repair_car(
+    mut commands: Commands,
+    mut cars_q: Query<(Entity, &CarInput, &mut CarDamage)>,
+    children_q: Query<&Children>,
+    mut meshes_q: Query<(&mut Handle<Mesh>, &Dented)>,
+) {
+    for (car, input, mut damage) in &mut cars_q {
+        if !input.repair {
+            continue;
+        }
+        *damage = CarDamage::default();
+
+        for entity in children_q.iter_descendants(car) {
+            if let Ok((mut handle, dented)) = meshes_q.get_mut(entity) {
+                *handle = dented.original.clone();
+                commands.entity(entity).remove::<Dented>();
+            }
+        }
+        info!("repaired the car");
+    }
+}
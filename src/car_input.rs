@@ -0,0 +1,113 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::PlayerCar;
+
+/// The commands a driver sends to a car, the vehicle systems only read this.
+#[derive(Component, Reflect, Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct CarInput {
+    pub accelerate: bool,
+    pub reverse: bool,
+    pub steer_left: bool,
+    pub steer_right: bool,
+    /// Repairs the car, only for the frame it is asked.
+    pub repair: bool,
+}
+
+/// The keys driving the player car.
+#[derive(Resource, Reflect, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct CarControls {
+    pub accelerate: KeyCode,
+    pub reverse: KeyCode,
+    pub steer_left: KeyCode,
+    pub steer_right: KeyCode,
+    pub repair: KeyCode,
+}
+
+impl Default for CarControls {
+    fn default() -> CarControls {
+        CarControls {
+            accelerate: KeyCode::Up,
+            reverse: KeyCode::Down,
+            steer_left: KeyCode::Left,
+            steer_right: KeyCode::Right,
+            repair: KeyCode::R,
+        }
+    }
+}
+
+/// One of the actions bound to a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Control {
+    Accelerate,
+    Reverse,
+    SteerLeft,
+    SteerRight,
+    Repair,
+}
+
+impl Control {
+    pub const ALL: [Control; 5] = [
+        Control::Accelerate,
+        Control::Reverse,
+        Control::SteerLeft,
+        Control::SteerRight,
+        Control::Repair,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Control::Accelerate => "Accelerate",
+            Control::Reverse => "Reverse",
+            Control::SteerLeft => "Steer left",
+            Control::SteerRight => "Steer right",
+            Control::Repair => "Repair",
+        }
+    }
+}
+
+impl CarControls {
+    pub fn key(&self, control: Control) -> KeyCode {
+        match control {
+            Control::Accelerate => self.accelerate,
+            Control::Reverse => self.reverse,
+            Control::SteerLeft => self.steer_left,
+            Control::SteerRight => self.steer_right,
+            Control::Repair => self.repair,
+        }
+    }
+
+    pub fn bind(&mut self, control: Control, key: KeyCode) {
+        let bound = match control {
+            Control::Accelerate => &mut self.accelerate,
+            Control::Reverse => &mut self.reverse,
+            Control::SteerLeft => &mut self.steer_left,
+            Control::SteerRight => &mut self.steer_right,
+            Control::Repair => &mut self.repair,
+        };
+        *bound = key;
+    }
+}
+
+pub fn update_car_input_from_keyboard(
+    keys: Res<Input<KeyCode>>,
+    controls: Res<CarControls>,
+    mut car_query: Query<&mut CarInput, With<PlayerCar>>,
+) {
+    let Ok(mut car_input) = car_query.get_single_mut() else {
+        return;
+    };
+
+    let new_input = CarInput {
+        accelerate: keys.pressed(controls.accelerate),
+        reverse: keys.pressed(controls.reverse),
+        steer_left: keys.pressed(controls.steer_left),
+        steer_right: keys.pressed(controls.steer_right),
+        repair: keys.just_pressed(controls.repair),
+    };
+
+    // Avoid triggering change detection every frame
+    car_input.set_if_neq(new_input);
+}
@@ -2,7 +2,10 @@ use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::*;
 use interpolation::Lerp;
 
+use crate::car_damage::CarDamage;
 use crate::car_suspension::CarPhysics;
+use crate::map::Surface;
+use crate::telemetry::WheelTelemetry;
 use crate::{CarWheel, RayCastWheelEntity};
 
 pub fn update_car_steering(
@@ -11,45 +14,58 @@ pub fn update_car_steering(
         &LinearVelocity,
         &AngularVelocity,
         &CarPhysics,
+        &CarDamage,
         &mut ExternalForce,
         &Transform,
         &CenterOfMass,
     )>,
     wheels_transforms_query: Query<&CarWheel, Without<CarPhysics>>,
-    raycast_query: Query<(&RayCastWheelEntity, &RayCaster, &RayHits)>,
+    mut raycast_query: Query<(
+        &Parent,
+        &RayCastWheelEntity,
+        &RayCaster,
+        &RayHits,
+        &mut WheelTelemetry,
+    )>,
+    surface_query: Query<&Surface>,
 ) {
-    let Ok((
-        &LinearVelocity(lin_vel),
-        &AngularVelocity(ang_vel),
-        car_physics,
-        mut external_force,
-        &car_transform,
-        &CenterOfMass(car_center_of_mass),
-    )) = car_query.get_single_mut()
-    else {
-        return;
-    };
-
-    let CarPhysics {
-        tire_mass,
-        front_tire_max_grip_factor,
-        back_tire_max_grip_factor,
-        front_tire_min_grip_factor,
-        back_tire_min_grip_factor,
-        tire_grip_velocity_multiplier,
-        wheel_rotation,
-        top_speed,
-        ..
-    } = *car_physics;
-
-    for (&RayCastWheelEntity(entity), ray, hits) in &raycast_query {
-        let car_wheel = wheels_transforms_query.get(entity).unwrap();
+    for (car, &RayCastWheelEntity(entity), ray, hits, mut telemetry) in &mut raycast_query {
+        let Ok((
+            &LinearVelocity(lin_vel),
+            &AngularVelocity(ang_vel),
+            car_physics,
+            damage,
+            mut external_force,
+            &car_transform,
+            &CenterOfMass(car_center_of_mass),
+        )) = car_query.get_mut(car.get())
+        else {
+            continue;
+        };
+
+        let CarPhysics {
+            tire_mass,
+            front_tire_max_grip_factor,
+            back_tire_max_grip_factor,
+            front_tire_min_grip_factor,
+            back_tire_min_grip_factor,
+            tire_grip_velocity_multiplier,
+            wheel_rotation,
+            top_speed,
+            ..
+        } = *car_physics;
+
+        let &car_wheel = wheels_transforms_query.get(entity).unwrap();
 
         assert!(hits.len() <= 1);
         let hit = hits.as_slice().get(0);
 
+        telemetry.lateral_velocity = 0.0;
+        telemetry.grip_factor = 0.0;
+        telemetry.lateral_force = 0.0;
+
         // steering force
-        if hit.is_some() {
+        if let Some(hit) = hit {
             // World-space direction of the spring force
             let steering_dir = if matches!(car_wheel, CarWheel::FrontLeft | CarWheel::FrontRight) {
                 if wheel_rotation <= 0.5 {
@@ -88,6 +104,10 @@ pub fn update_car_steering(
                     )
                 };
 
+            // The ground the tire is on changes its grip
+            let surface = surface_query.get(hit.entity).copied().unwrap_or_default();
+            let tire_grip_factor = tire_grip_factor * surface.grip() * damage.grip(car_wheel);
+
             // The change in velocity that we're loking for is -steering_vel * grip_factor
             // grip_factor is in range 0-1, 0 means no grip, 1 means full grip
             let desired_vel_change = -steering_vel * tire_grip_factor;
@@ -98,9 +118,15 @@ pub fn update_car_steering(
             let desired_accel = desired_vel_change / time.delta_seconds();
 
             // Force = Mass * Acceleration, so multiply by the mass of the tire and apply as a force!
+            let lateral_force = tire_mass * desired_accel;
+
+            telemetry.lateral_velocity = steering_vel;
+            telemetry.grip_factor = tire_grip_factor;
+            telemetry.lateral_force = lateral_force;
+
             external_force.persistent = false;
             external_force.apply_force_at_point(
-                steering_dir * tire_mass * desired_accel,
+                steering_dir * lateral_force,
                 car_transform.rotation * ray.origin,
                 car_center_of_mass,
             );
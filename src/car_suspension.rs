@@ -3,10 +3,12 @@ use bevy_inspector_egui::inspector_options::ReflectInspectorOptions;
 use bevy_inspector_egui::InspectorOptions;
 use bevy_xpbd_3d::prelude::*;
 
-use crate::RayCastWheelEntity;
+use crate::car_damage::CarDamage;
+use crate::telemetry::WheelTelemetry;
+use crate::{CarWheel, RayCastWheelEntity};
 
 #[derive(Component, Reflect, InspectorOptions)]
-#[reflect(InspectorOptions)]
+#[reflect(Component, InspectorOptions)]
 pub struct CarPhysics {
     pub chassis_size: Vec3,
     pub max_suspension: f32,
@@ -37,30 +39,46 @@ pub fn update_car_suspension(
         &LinearVelocity,
         &AngularVelocity,
         &mut CarPhysics,
+        &CarDamage,
         &mut ExternalForce,
         &Transform,
         &CenterOfMass,
     )>,
-    raycast_query: Query<(&RayCastWheelEntity, &RayCaster, &RayHits)>,
+    wheels_query: Query<&CarWheel>,
+    mut raycast_query: Query<(
+        &Parent,
+        &RayCastWheelEntity,
+        &RayCaster,
+        &RayHits,
+        &mut WheelTelemetry,
+    )>,
 ) {
-    let Ok((
-        &LinearVelocity(lin_vel),
-        &AngularVelocity(ang_vel),
-        car_physics,
-        mut external_force,
-        &car_transform,
-        &CenterOfMass(car_center_of_mass),
-    )) = car_query.get_single_mut()
-    else {
-        return;
-    };
-
-    let CarPhysics { max_suspension, suspension_strength, suspension_damping, .. } = *car_physics;
-
-    for (_, ray, hits) in &raycast_query {
+    for (car, &RayCastWheelEntity(entity), ray, hits, mut telemetry) in &mut raycast_query {
+        let Ok((
+            &LinearVelocity(lin_vel),
+            &AngularVelocity(ang_vel),
+            car_physics,
+            damage,
+            mut external_force,
+            &car_transform,
+            &CenterOfMass(car_center_of_mass),
+        )) = car_query.get_mut(car.get())
+        else {
+            continue;
+        };
+
+        let CarPhysics { max_suspension, suspension_strength, suspension_damping, .. } =
+            *car_physics;
+        // A bent wheel sags
+        let suspension_strength = suspension_strength
+            * wheels_query.get(entity).map_or(1.0, |&wheel| damage.suspension(wheel));
+
         assert!(hits.len() <= 1);
         let hit = hits.as_slice().get(0);
 
+        telemetry.compression = 0.0;
+        telemetry.spring_force = 0.0;
+
         // suspension spring force
         if let Some(RayHitData { time_of_impact, .. }) = hit {
             // World-space direction of the spring force.
@@ -80,6 +98,9 @@ pub fn update_car_suspension(
             // Calculate he magnitude of the dampened spring force!
             let force = (offset * suspension_strength) - (vel * suspension_damping);
 
+            telemetry.compression = offset / max_suspension;
+            telemetry.spring_force = force;
+
             // Apply force at the location of this tire, in the direction
             // of the suspension.
             external_force.persistent = false;
@@ -4,74 +4,68 @@ use bevy::prelude::*;
 use bevy_xpbd_3d::prelude::*;
 use interpolation::Lerp;
 
+use crate::car_damage::CarDamage;
+use crate::car_input::CarInput;
 use crate::car_suspension::CarPhysics;
 use crate::{CarWheel, RayCastWheelEntity};
 
 pub fn update_car_wheel_rotation_speed(
     mut car_query: Query<(&mut CarPhysics, &LinearVelocity, &Transform)>,
 ) {
-    let Ok((mut car_physics, &LinearVelocity(lin_vel), car_transform)) = car_query.get_single_mut()
-    else {
-        return;
-    };
+    for (mut car_physics, &LinearVelocity(lin_vel), car_transform) in &mut car_query {
+        let CarPhysics { wheel_rotation_speed, top_speed, .. } = car_physics.as_mut();
 
-    let CarPhysics { wheel_rotation_speed, top_speed, .. } = car_physics.as_mut();
+        // Forward speed of the car (in the direction of driving)
+        let car_speed = car_transform.forward().dot(lin_vel);
+        // Normalized car speed
+        let normalized_speed = (car_speed.abs() / *top_speed).clamp(0.0, 1.0);
 
-    // Forward speed of the car (in the direction of driving)
-    let car_speed = car_transform.forward().dot(lin_vel);
-    // Normalized car speed
-    let normalized_speed = (car_speed.abs() / *top_speed).clamp(0.0, 1.0);
+        // The faster you go the slower the wheel rotation speed
+        let increased_normalized_speed = (normalized_speed * 10.0).clamp(0.0, 1.0);
 
-    // The faster you go the slower the wheel rotation speed
-    let increased_normalized_speed = (normalized_speed * 10.0).clamp(0.0, 1.0);
-
-    *wheel_rotation_speed = 0.1.lerp(&1.5, &(1.0 - increased_normalized_speed));
+        *wheel_rotation_speed = 0.1.lerp(&1.5, &(1.0 - increased_normalized_speed));
+    }
 }
 
 pub fn update_car_wheel_control(
     time: Res<Time>,
-    keys: Res<Input<KeyCode>>,
-    mut car_query: Query<&mut CarPhysics>,
+    mut car_query: Query<(&mut CarPhysics, &CarDamage, &CarInput)>,
 ) {
-    let Ok(mut car_physics) = car_query.get_single_mut() else {
-        return;
-    };
+    for (mut car_physics, damage, car_input) in &mut car_query {
+        let CarPhysics { wheel_rotation, wheel_rotation_speed, .. } = car_physics.as_mut();
 
-    let CarPhysics { wheel_rotation, wheel_rotation_speed, .. } = car_physics.as_mut();
+        if car_input.steer_left {
+            *wheel_rotation -= *wheel_rotation_speed * time.delta_seconds();
+        }
+        if car_input.steer_right {
+            *wheel_rotation += *wheel_rotation_speed * time.delta_seconds();
+        }
 
-    if keys.pressed(KeyCode::Left) {
-        *wheel_rotation -= *wheel_rotation_speed * time.delta_seconds();
-    }
-    if keys.pressed(KeyCode::Right) {
-        *wheel_rotation += *wheel_rotation_speed * time.delta_seconds();
-    }
+        // Move the wheels back to position, off straight when they are bent
+        if !car_input.steer_left && !car_input.steer_right {
+            let straight = damage.straight_wheel_rotation();
+            *wheel_rotation = if *wheel_rotation <= straight {
+                (*wheel_rotation + *wheel_rotation_speed * time.delta_seconds()).min(straight)
+            } else {
+                (*wheel_rotation - *wheel_rotation_speed * time.delta_seconds()).max(straight)
+            };
+        }
 
-    // Move the wheels back to position
-    if !keys.pressed(KeyCode::Left) && !keys.pressed(KeyCode::Right) {
-        *wheel_rotation = if *wheel_rotation <= 0.5 {
-            (*wheel_rotation + *wheel_rotation_speed * time.delta_seconds()).min(0.5)
-        } else {
-            (*wheel_rotation - *wheel_rotation_speed * time.delta_seconds()).max(0.5)
-        };
+        *wheel_rotation = wheel_rotation.clamp(0.2, 0.8);
     }
-
-    *wheel_rotation = wheel_rotation.clamp(0.2, 0.8);
 }
 
 pub fn update_car_wheels(
-    mut car_query: Query<&CarPhysics, Without<CarWheel>>,
+    car_query: Query<&CarPhysics, Without<CarWheel>>,
     mut wheels_transforms_query: Query<(&CarWheel, &mut Transform), Without<CarPhysics>>,
-    raycast_query: Query<(&RayCastWheelEntity, &RayCaster, &RayHits)>,
+    raycast_query: Query<(&Parent, &RayCastWheelEntity, &RayHits)>,
 ) {
-    let Ok(car_physics) = car_query.get_single_mut() else {
-        return;
-    };
-
-    let CarPhysics { wheel_rotation, max_suspension, .. } = *car_physics;
-
     let wheel_half_height = 0.3;
 
-    for (&RayCastWheelEntity(entity), _, hits) in &raycast_query {
+    for (car, &RayCastWheelEntity(entity), hits) in &raycast_query {
+        let Ok(car_physics) = car_query.get(car.get()) else { continue };
+        let CarPhysics { wheel_rotation, max_suspension, .. } = *car_physics;
+
         let (car_wheel, mut wheel_transform) = wheels_transforms_query.get_mut(entity).unwrap();
 
         assert!(hits.len() <= 1);
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::score::EndlessScore;
+use crate::telemetry::WheelTelemetry;
+use crate::{CarWheel, PlayerCar, RayCastWheelEntity};
+
+/// The body slip angle, in degrees, starting a drift and the one below which it ends.
+const START_ANGLE: f32 = 15.0;
+const END_ANGLE: f32 = 8.0;
+
+/// A body slip angle, in degrees, beyond which the car spun out and the drift is lost.
+const SPIN_ANGLE: f32 = 100.0;
+
+/// How fast the rear tires slide sideways, in m/s, the `steering_vel` of the steering system.
+const REAR_SLIP: f32 = 2.0;
+
+/// How fast the car must go, in m/s, to drift.
+const MIN_SPEED: f32 = 10.0;
+
+/// How long a drift can straighten up before ending, in seconds.
+const END_GRACE: f32 = 0.3;
+
+/// How long a drift lasts at least to score, in seconds.
+const MIN_DURATION: f32 = 1.0;
+
+/// The points of a second of drift at the reference angle and speed.
+const POINTS_PER_SECOND: f32 = 50.0;
+const REFERENCE_ANGLE: f32 = 30.0;
+const REFERENCE_SPEED: f32 = 20.0;
+
+/// How soon after a drift the next one extends its chain, and how much every drift of
+/// the chain adds to the multiplier, up to the maximum.
+const CHAIN_TIME: f32 = 2.0;
+const CHAIN_STEP: f32 = 0.25;
+const MAX_CHAIN_MULTIPLIER: f32 = 3.0;
+
+/// What a drift was like, its angles in degrees and speeds in m/s.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DriftMeasures {
+    pub duration: f32,
+    pub mean_angle: f32,
+    pub max_angle: f32,
+    pub mean_speed: f32,
+}
+
+/// The drift a car is in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CurrentDrift {
+    pub measures: DriftMeasures,
+    /// Whether the rear slides to the right.
+    pub to_the_right: bool,
+    /// When the angle went below the end angle.
+    straight_since: Option<f32>,
+}
+
+impl CurrentDrift {
+    /// The points of the drift before its chain multiplier.
+    pub fn points(&self) -> f32 {
+        let DriftMeasures { duration, mean_angle, mean_speed, .. } = self.measures;
+        duration
+            * POINTS_PER_SECOND
+            * (mean_angle / REFERENCE_ANGLE).min(2.0)
+            * (mean_speed / REFERENCE_SPEED).min(2.0)
+    }
+}
+
+/// Detects the drifts of a car from its body slip angle, the angle between where it
+/// points and where it goes.
+#[derive(Component, Debug, Default)]
+pub struct Drift {
+    /// The body slip angle in degrees, positive when the rear slides to the right.
+    pub slip_angle: f32,
+    pub current: Option<CurrentDrift>,
+    /// The number of drifts linked before the current one.
+    pub chain: u32,
+    chain_until: f32,
+}
+
+impl Drift {
+    pub fn chain_multiplier(&self) -> f32 {
+        (1.0 + self.chain as f32 * CHAIN_STEP).min(MAX_CHAIN_MULTIPLIER)
+    }
+}
+
+/// The drifts of the cars, for the score, the HUD and the sounds.
+#[derive(Event, Debug, Clone, Copy)]
+pub enum DriftEvent {
+    Started {
+        car: Entity,
+        chain: u32,
+    },
+    /// A drift long enough to score, with its points and the multiplier of its chain.
+    Ended {
+        car: Entity,
+        measures: DriftMeasures,
+        points: f32,
+        multiplier: f32,
+    },
+    /// The drift was too short or the car spun out, it breaks the chain.
+    Lost {
+        car: Entity,
+    },
+}
+
+/// Follows the body slip angle of the cars to detect when they drift and measure it, on
+/// the physics clock stopped with the simulation.
+pub fn track_drifts(
+    time: Res<Time<Physics>>,
+    mut drift_events: EventWriter<DriftEvent>,
+    mut cars_q: Query<(Entity, &mut Drift, &Transform, &LinearVelocity)>,
+    wheels_q: Query<&CarWheel>,
+    telemetry_q: Query<(&Parent, &RayCastWheelEntity, &WheelTelemetry)>,
+) {
+    let now = time.elapsed_seconds();
+    let dt = time.delta_seconds();
+
+    // Whether the rear tires of every car touch the ground and slide
+    let mut rear_sliding = HashMap::new();
+    for (car, &RayCastWheelEntity(wheel), telemetry) in &telemetry_q {
+        if !matches!(wheels_q.get(wheel), Ok(CarWheel::BackLeft | CarWheel::BackRight)) {
+            continue;
+        }
+        let sliding = telemetry.compression > 0.0 && telemetry.lateral_velocity.abs() > REAR_SLIP;
+        *rear_sliding.entry(car.get()).or_insert(false) |= sliding;
+    }
+
+    for (car, mut drift, transform, &LinearVelocity(lin_vel)) in &mut cars_q {
+        let local = transform.rotation.inverse() * lin_vel;
+        let speed = local.xz().length();
+        // Zero when going where the car points, the car goes to the right of where it
+        // points when its rear slides to the right
+        drift.slip_angle =
+            if speed > f32::EPSILON { local.x.atan2(-local.z).to_degrees() } else { 0.0 };
+        let angle = drift.slip_angle.abs();
+        let sliding = rear_sliding.get(&car).copied().unwrap_or(false) && speed >= MIN_SPEED;
+
+        let Some(mut current) = drift.current else {
+            if sliding && angle >= START_ANGLE && angle < SPIN_ANGLE {
+                if now > drift.chain_until {
+                    drift.chain = 0;
+                }
+                drift.current =
+                    Some(CurrentDrift { to_the_right: drift.slip_angle > 0.0, ..default() });
+                drift_events.send(DriftEvent::Started { car, chain: drift.chain });
+            }
+            continue;
+        };
+
+        if angle >= SPIN_ANGLE {
+            drift.current = None;
+            drift.chain = 0;
+            drift_events.send(DriftEvent::Lost { car });
+            continue;
+        }
+
+        let measures = &mut current.measures;
+        let previous = measures.duration;
+        let duration = previous + dt;
+        let mean = |mean: f32, value: f32| (mean * previous + value * dt) / duration;
+        measures.duration = duration;
+        measures.mean_angle = mean(measures.mean_angle, angle);
+        measures.mean_speed = mean(measures.mean_speed, speed);
+        measures.max_angle = measures.max_angle.max(angle);
+        current.to_the_right = drift.slip_angle > 0.0;
+
+        let straight = !sliding || angle < END_ANGLE;
+        current.straight_since = if straight { current.straight_since.or(Some(now)) } else { None };
+        drift.current = Some(current);
+        if current.straight_since.map_or(true, |since| now - since < END_GRACE) {
+            continue;
+        }
+
+        drift.current = None;
+        if current.measures.duration < MIN_DURATION {
+            drift.chain = 0;
+            drift_events.send(DriftEvent::Lost { car });
+            continue;
+        }
+        let multiplier = drift.chain_multiplier();
+        drift_events.send(DriftEvent::Ended {
+            car,
+            measures: current.measures,
+            points: current.points() * multiplier,
+            multiplier,
+        });
+        drift.chain += 1;
+        drift.chain_until = now + CHAIN_TIME;
+    }
+}
+
+/// Scores the drifts of the player on the endless road.
+pub fn score_drifts(
+    time: Res<Time<Physics>>,
+    mut drift_events: EventReader<DriftEvent>,
+    mut score: ResMut<EndlessScore>,
+    player_q: Query<Entity, With<PlayerCar>>,
+) {
+    let Ok(player) = player_q.get_single() else { return };
+    for event in drift_events.read() {
+        if let &DriftEvent::Ended { car, points, .. } = event {
+            if car == player && score.game_over.is_none() {
+                score.award(time.elapsed_seconds(), "drift", points);
+            }
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::road::{road_material, spawn_road_geometry};
+use crate::road_geometry::{
+    build_road_geometry, sample_spline_section, RoadPoint, RoadProfile, RoadSample,
+};
+use crate::{PlayerCar, RaceEntity};
+
+/// The distance between two control points of the endless road.
+const SEGMENT_LENGTH: f32 = 30.0;
+
+/// The number of segments built at once.
+const CHUNK_SEGMENTS: usize = 4;
+
+/// How far ahead of the car the road is built.
+const GENERATE_AHEAD: f32 = 500.0;
+
+/// How far behind the car the road is kept before being despawned.
+const KEEP_BEHIND: f32 = 200.0;
+
+/// The number of straight segments the road starts with.
+const STRAIGHT_START: usize = 3;
+
+/// How high the ramp before a jump rises, and how long the gap after it is.
+const JUMP_RAMP_HEIGHT: f32 = 2.5;
+const JUMP_GAP_LENGTH: f32 = 20.0;
+
+/// How the endless road gets harder with the distance.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DifficultyCurve {
+    /// The same seed always builds the same road.
+    #[serde(default)]
+    pub seed: u64,
+    /// Sorted by distance, the difficulty is interpolated between them.
+    pub steps: Vec<Difficulty>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Difficulty {
+    /// The distance along the road, in meters, where this difficulty is reached.
+    pub distance: f32,
+    /// The largest change of heading between two control points, in degrees.
+    pub max_turn: f32,
+    /// The largest change of elevation between two control points, in meters.
+    pub max_elevation_change: f32,
+    /// The banking of the sharpest turns, in degrees.
+    #[serde(default)]
+    pub max_banking: f32,
+    pub lanes: u32,
+    pub lane_width: f32,
+    /// The chance of a jump after each control point.
+    #[serde(default)]
+    pub jump_chance: f32,
+}
+
+impl DifficultyCurve {
+    /// The difficulty at the given distance, the curve must have at least one step.
+    pub fn at(&self, distance: f32) -> Difficulty {
+        let Some(next) = self.steps.iter().position(|step| step.distance > distance) else {
+            return self.steps[self.steps.len() - 1];
+        };
+        if next == 0 {
+            return self.steps[0];
+        }
+
+        let (from, to) = (self.steps[next - 1], self.steps[next]);
+        let t = (distance - from.distance) / (to.distance - from.distance);
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        Difficulty {
+            distance,
+            max_turn: lerp(from.max_turn, to.max_turn),
+            max_elevation_change: lerp(from.max_elevation_change, to.max_elevation_change),
+            max_banking: lerp(from.max_banking, to.max_banking),
+            lanes: lerp(from.lanes as f32, to.lanes as f32).round() as u32,
+            lane_width: lerp(from.lane_width, to.lane_width),
+            jump_chance: lerp(from.jump_chance, to.jump_chance),
+        }
+    }
+}
+
+/// A xorshift generator, enough to vary the road and its traffic.
+pub struct RoadRng(u64);
+
+impl RoadRng {
+    pub fn new(seed: u64) -> RoadRng {
+        RoadRng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in [0, 1).
+    pub fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in [-1, 1).
+    pub fn signed(&mut self) -> f32 {
+        self.unit() * 2.0 - 1.0
+    }
+}
+
+/// The road of the endless maps, built in chunks ahead of the car.
+#[derive(Resource)]
+pub struct EndlessRoad {
+    curve: DifficultyCurve,
+    rng: RoadRng,
+    points: Vec<RoadPoint>,
+    /// Whether the segment from the control point of the same index is a gap to jump over.
+    gaps: Vec<bool>,
+    heading: f32,
+    /// The first segment of the next chunk, and the road distance where it starts.
+    next_segment: usize,
+    generated_distance: f32,
+    chunks: VecDeque<RoadChunk>,
+    material: Option<Handle<StandardMaterial>>,
+    /// How far along the road the car is.
+    pub distance: f32,
+    /// The furthest the car went along the road.
+    pub furthest: f32,
+}
+
+struct RoadChunk {
+    entities: Vec<Entity>,
+    samples: Vec<RoadSample>,
+    end_distance: f32,
+}
+
+/// The parts of a chunk between its gaps.
+struct ChunkRuns {
+    runs: Vec<Vec<RoadSample>>,
+    profile: RoadProfile,
+}
+
+fn heading_direction(heading: f32) -> Vec3 {
+    Vec3::new(-heading.sin(), 0.0, -heading.cos())
+}
+
+impl EndlessRoad {
+    /// Starts the road on the ground under the car, a segment behind it.
+    pub fn new(curve: DifficultyCurve, start: &Transform) -> EndlessRoad {
+        let forward = start.forward();
+        let heading = (-forward.x).atan2(-forward.z);
+        let mut position = start.translation - heading_direction(heading) * SEGMENT_LENGTH;
+        position.y = 0.0;
+
+        let first = curve.at(0.0);
+        let width = first.lanes as f32 * first.lane_width;
+        EndlessRoad {
+            rng: RoadRng::new(curve.seed),
+            curve,
+            points: vec![RoadPoint::new(position, width, 0.0)],
+            gaps: Vec::new(),
+            heading,
+            next_segment: 0,
+            generated_distance: 0.0,
+            chunks: VecDeque::new(),
+            material: None,
+            distance: 0.0,
+            furthest: 0.0,
+        }
+    }
+
+    /// Moves the road with the floating origin.
+    pub fn shift(&mut self, offset: Vec3) {
+        for point in &mut self.points {
+            point.position = (point.position() + offset).to_array();
+        }
+        for sample in self.chunks.iter_mut().flat_map(|chunk| &mut chunk.samples) {
+            sample.position += offset;
+        }
+    }
+
+    /// The cross-section of the road built so far nearest to a position.
+    pub fn nearest_sample(&self, position: Vec3) -> Option<&RoadSample> {
+        self.chunks.iter().flat_map(|chunk| &chunk.samples).min_by(|a, b| {
+            a.position.distance_squared(position).total_cmp(&b.position.distance_squared(position))
+        })
+    }
+
+    fn push_point(&mut self, offset: Vec3, difficulty: &Difficulty, banking: f32, gap: bool) {
+        let last = self.points[self.points.len() - 1].position();
+        let width = difficulty.lanes.max(1) as f32 * difficulty.lane_width;
+        self.points.push(RoadPoint::new(last + offset, width, banking));
+        self.gaps.push(gap);
+    }
+
+    /// Adds a control point, or a ramp and the landing after its gap.
+    fn extend(&mut self) {
+        let index = self.points.len();
+        let difficulty = self.curve.at(index as f32 * SEGMENT_LENGTH);
+        let calm = index <= STRAIGHT_START || self.gaps.last() == Some(&true);
+
+        if !calm && self.rng.unit() < difficulty.jump_chance {
+            let direction = heading_direction(self.heading);
+            let ramp = direction * SEGMENT_LENGTH + Vec3::Y * JUMP_RAMP_HEIGHT;
+            self.push_point(ramp, &difficulty, 0.0, false);
+            let landing = direction * JUMP_GAP_LENGTH - Vec3::Y * JUMP_RAMP_HEIGHT;
+            self.push_point(landing, &difficulty, 0.0, true);
+            return;
+        }
+
+        let (turn, rise) = if calm { (0.0, 0.0) } else { (self.rng.signed(), self.rng.signed()) };
+        self.heading += (turn * difficulty.max_turn).to_radians();
+        let offset = heading_direction(self.heading) * SEGMENT_LENGTH
+            + Vec3::Y * rise * difficulty.max_elevation_change;
+        // Lean into the turn, a positive turn is to the left
+        self.push_point(offset, &difficulty, -turn * difficulty.max_banking, false);
+    }
+
+    /// Samples the next chunk, split around its gaps.
+    fn next_chunk(&mut self) -> ChunkRuns {
+        let segments = self.next_segment..self.next_segment + CHUNK_SEGMENTS;
+        // The spline through the last segment needs the point after its end
+        while self.points.len() < segments.end + 2 {
+            self.extend();
+        }
+
+        let difficulty = self.curve.at(self.generated_distance);
+        let profile = RoadProfile { lanes: difficulty.lanes.max(1), ..default() };
+
+        let mut runs = Vec::new();
+        let mut run_start = segments.start;
+        for segment in segments.clone() {
+            if !self.gaps[segment] {
+                continue;
+            }
+            runs.extend(self.sample_run(run_start..segment));
+            let gap = self.points[segment].position().distance(self.points[segment + 1].position());
+            self.generated_distance += gap;
+            run_start = segment + 1;
+        }
+        runs.extend(self.sample_run(run_start..segments.end));
+
+        self.next_segment = segments.end;
+        ChunkRuns { runs, profile }
+    }
+
+    fn sample_run(&mut self, segments: Range<usize>) -> Option<Vec<RoadSample>> {
+        if segments.is_empty() {
+            return None;
+        }
+        let mut samples = sample_spline_section(&self.points, segments);
+        for sample in &mut samples {
+            sample.distance += self.generated_distance;
+        }
+        self.generated_distance = samples.last()?.distance;
+        Some(samples)
+    }
+}
+
+/// Builds the road ahead of the car and despawns the one far behind it.
+pub fn extend_endless_road(
+    mut commands: Commands,
+    mut road: ResMut<EndlessRoad>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = road.material.get_or_insert_with(|| road_material(&mut materials)).clone();
+
+    while road.generated_distance < road.furthest + GENERATE_AHEAD {
+        let ChunkRuns { runs, profile } = road.next_chunk();
+        let mut entities = Vec::new();
+        for samples in &runs {
+            match build_road_geometry(samples, &profile) {
+                Ok(geometry) => {
+                    let entity =
+                        spawn_road_geometry(&mut commands, &mut meshes, material.clone(), geometry);
+                    commands.entity(entity).insert(RaceEntity);
+                    entities.push(entity);
+                }
+                Err(e) => error!("could not build the endless road: {e}"),
+            }
+        }
+        let end_distance = road.generated_distance;
+        road.chunks.push_back(RoadChunk { entities, samples: runs.concat(), end_distance });
+    }
+
+    while road
+        .chunks
+        .front()
+        .map_or(false, |chunk| chunk.end_distance < road.furthest - KEEP_BEHIND)
+    {
+        let Some(chunk) = road.chunks.pop_front() else { break };
+        for entity in chunk.entities {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+/// Projects the car on the nearest cross-section of the road to know how far it went.
+pub fn track_endless_distance(
+    mut road: ResMut<EndlessRoad>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    let Ok(car_transform) = car_q.get_single() else { return };
+    let position = car_transform.translation;
+    let Some(&nearest) = road.nearest_sample(position) else { return };
+
+    road.distance = nearest.distance + (position - nearest.position).dot(nearest.forward);
+    road.furthest = road.furthest.max(road.distance);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(distance: f32, max_turn: f32, lanes: u32) -> Difficulty {
+        Difficulty {
+            distance,
+            max_turn,
+            max_elevation_change: 0.0,
+            max_banking: 0.0,
+            lanes,
+            lane_width: 3.5,
+            jump_chance: 0.0,
+        }
+    }
+
+    fn curve() -> DifficultyCurve {
+        DifficultyCurve { seed: 0, steps: vec![step(100.0, 10.0, 2), step(300.0, 30.0, 4)] }
+    }
+
+    /// Calm up to 300 m, then jumping more and more until a jump after every landing.
+    fn jumpy() -> DifficultyCurve {
+        let mut far = step(600.0, 30.0, 2);
+        far.max_elevation_change = 2.0;
+        far.jump_chance = 1.0;
+        DifficultyCurve { seed: 3, steps: vec![step(300.0, 10.0, 2), far] }
+    }
+
+    /// Builds the chunks like the system does, without their meshes.
+    fn generate(road: &mut EndlessRoad, count: usize) -> Vec<Vec<RoadSample>> {
+        let mut all_runs = Vec::new();
+        for _ in 0..count {
+            let ChunkRuns { runs, .. } = road.next_chunk();
+            let end_distance = road.generated_distance;
+            road.chunks.push_back(RoadChunk {
+                entities: Vec::new(),
+                samples: runs.concat(),
+                end_distance,
+            });
+            all_runs.extend(runs);
+        }
+        all_runs
+    }
+
+    #[test]
+    fn interpolates_between_steps() {
+        let difficulty = curve().at(200.0);
+        assert_eq!(difficulty.distance, 200.0);
+        assert!((difficulty.max_turn - 20.0).abs() < 1e-4);
+        assert_eq!(difficulty.lanes, 3);
+    }
+
+    #[test]
+    fn clamps_outside_the_steps() {
+        let curve = curve();
+        assert_eq!(curve.at(0.0).max_turn, 10.0);
+        assert_eq!(curve.at(100.0).max_turn, 10.0);
+        assert_eq!(curve.at(300.0).max_turn, 30.0);
+        assert_eq!(curve.at(10_000.0).lanes, 4);
+    }
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let (mut a, mut b) = (RoadRng::new(42), RoadRng::new(42));
+        for _ in 0..100 {
+            assert_eq!(a.next(), b.next());
+        }
+        assert_ne!(RoadRng::new(1).next(), RoadRng::new(2).next());
+    }
+
+    #[test]
+    fn zero_seed_still_varies() {
+        let mut rng = RoadRng::new(0);
+        let first = rng.next();
+        assert_ne!(first, 0);
+        assert_ne!(first, rng.next());
+    }
+
+    #[test]
+    fn unit_and_signed_ranges() {
+        let mut rng = RoadRng::new(7);
+        for _ in 0..1000 {
+            assert!((0.0..1.0).contains(&rng.unit()));
+            assert!((-1.0..1.0).contains(&rng.signed()));
+        }
+    }
+
+    #[test]
+    fn chunks_join_without_seams() {
+        let mut road = EndlessRoad::new(curve(), &Transform::IDENTITY);
+        let runs = generate(&mut road, 8);
+        // Without jumps every chunk is a single run
+        assert_eq!(runs.len(), 8);
+        assert!(road.gaps.iter().all(|&gap| !gap));
+
+        for pair in runs.windows(2) {
+            let (end, start) = (pair[0].last().unwrap(), pair[1].first().unwrap());
+            assert!(end.position.distance(start.position) < 1e-2);
+            assert!(end.forward.distance(start.forward) < 1e-3);
+            assert_eq!(end.distance, start.distance);
+        }
+        for run in &runs {
+            assert!(run.windows(2).all(|pair| pair[0].distance < pair[1].distance));
+        }
+        assert_eq!(runs.last().unwrap().last().unwrap().distance, road.generated_distance);
+    }
+
+    #[test]
+    fn gaps_come_with_the_jump_chance() {
+        let mut road = EndlessRoad::new(jumpy(), &Transform::IDENTITY);
+        let runs = generate(&mut road, 20);
+
+        for (segment, &gap) in road.gaps.iter().enumerate() {
+            if !gap {
+                continue;
+            }
+            // Behind a ramp, never before the chance rises nor right after a landing
+            assert!(segment as f32 * SEGMENT_LENGTH > 300.0);
+            assert!(!road.gaps[segment - 1]);
+            assert!(!road.gaps.get(segment + 1).copied().unwrap_or(false));
+        }
+        let far_gaps = road.gaps.iter().skip(25).filter(|&&gap| gap).count();
+        assert!(far_gaps > 10);
+
+        // The distance skips the gaps, the runs on both sides are a gap length apart
+        let landing = (JUMP_GAP_LENGTH.powi(2) + JUMP_RAMP_HEIGHT.powi(2)).sqrt();
+        let mut jumps = 0;
+        for pair in runs.windows(2) {
+            let (end, start) = (pair[0].last().unwrap(), pair[1].first().unwrap());
+            let apart = end.position.distance(start.position);
+            assert!((start.distance - end.distance - apart).abs() < 1e-2);
+            if apart > 1.0 {
+                assert!((apart - landing).abs() < 1e-2);
+                jumps += 1;
+            }
+        }
+        let generated_gaps = road.gaps[..road.next_segment].iter().filter(|&&gap| gap).count();
+        assert_eq!(jumps, generated_gaps);
+    }
+
+    #[test]
+    fn road_widens_with_the_difficulty() {
+        let mut road = EndlessRoad::new(curve(), &Transform::IDENTITY);
+        generate(&mut road, 6);
+
+        for (index, point) in road.points.iter().enumerate().skip(1) {
+            let lanes = road.curve.at(index as f32 * SEGMENT_LENGTH).lanes;
+            assert_eq!(point.width, lanes as f32 * 3.5);
+        }
+        assert_eq!(road.points[1].width, 7.0);
+        assert_eq!(road.points.last().unwrap().width, 14.0);
+    }
+
+    #[test]
+    fn tracks_the_furthest_distance() {
+        let mut road = EndlessRoad::new(curve(), &Transform::IDENTITY);
+        generate(&mut road, 4);
+        let sample_at = |distance: f32| {
+            *road
+                .chunks
+                .iter()
+                .flat_map(|chunk| &chunk.samples)
+                .find(|s| s.distance >= distance)
+                .unwrap()
+        };
+        let (ahead, behind) = (sample_at(200.0), sample_at(50.0));
+
+        let mut world = World::new();
+        world.insert_resource(road);
+        let car = world.spawn((PlayerCar, Transform::from_translation(ahead.position))).id();
+        let mut schedule = Schedule::default();
+        schedule.add_systems(track_endless_distance);
+
+        schedule.run(&mut world);
+        let road = world.resource::<EndlessRoad>();
+        assert!((road.distance - ahead.distance).abs() < 1e-3);
+        assert_eq!(road.furthest, road.distance);
+
+        // Driving back doesn't lose how far the car went
+        world.get_mut::<Transform>(car).unwrap().translation = behind.position;
+        schedule.run(&mut world);
+        let road = world.resource::<EndlessRoad>();
+        assert!((road.distance - behind.distance).abs() < 1e-3);
+        assert!((road.furthest - ahead.distance).abs() < 1e-3);
+    }
+}
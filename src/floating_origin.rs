@@ -0,0 +1,98 @@
+use bevy::math::DVec3;
+use bevy::prelude::*;
+use bevy_dolly::dolly_type::Rig;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::camera::{shift_rig, CameraMode, CameraTransition};
+use crate::endless::EndlessRoad;
+use crate::map::SpawnPoints;
+use crate::road_editor::RoadEditor;
+use crate::{MainCamera, PlayerCar};
+
+/// How far from the origin, horizontally, the car goes before the world is moved back
+/// around it.
+const RECENTER_DISTANCE: f32 = 1000.0;
+
+/// How far the world has been moved, to get the absolute positions back.
+#[derive(Resource, Default, Debug)]
+pub struct FloatingOrigin {
+    pub offset: DVec3,
+}
+
+impl FloatingOrigin {
+    /// The position before the world was ever moved.
+    pub fn absolute(&self, translation: Vec3) -> DVec3 {
+        translation.as_dvec3() - self.offset
+    }
+
+    /// Where an absolute position is in the moved world.
+    pub fn relative(&self, absolute: DVec3) -> Vec3 {
+        (absolute + self.offset).as_vec3()
+    }
+}
+
+/// Every race starts from the original origin.
+pub fn reset_floating_origin(mut origin: ResMut<FloatingOrigin>) {
+    *origin = FloatingOrigin::default();
+}
+
+/// Keeps the car close to the origin where f32 positions are precise, by moving the whole
+/// world around it on the ground plane.
+///
+/// Runs once the physics is synced and the camera updated, so the bodies, their transforms
+/// and the camera rig move together. The suspension rays only keep relative hit distances
+/// and are cast again from the moved bodies on the next step.
+pub fn recenter_world(
+    mut origin: ResMut<FloatingOrigin>,
+    mode: Res<CameraMode>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    endless_road: Option<ResMut<EndlessRoad>>,
+    road_editor: Option<ResMut<RoadEditor>>,
+    car_q: Query<Entity, With<PlayerCar>>,
+    camera_q: Query<Entity, With<MainCamera>>,
+    mut roots_q: Query<&mut Transform, (Without<Parent>, Without<Node>)>,
+    mut positions_q: Query<&mut Position>,
+    mut rigs_q: Query<&mut Rig>,
+    mut transitions_q: Query<&mut CameraTransition>,
+) {
+    let Ok(car) = car_q.get_single() else { return };
+    let Ok(&car_transform) = roots_q.get(car) else { return };
+    if car_transform.translation.xz().length() < RECENTER_DISTANCE {
+        return;
+    }
+
+    // Whole meters keep the moved positions as close as possible to where they were
+    let offset = Vec3::new(-car_transform.translation.x, 0.0, -car_transform.translation.z).round();
+    origin.offset += offset.as_dvec3();
+
+    for mut transform in &mut roots_q {
+        transform.translation += offset;
+    }
+    for mut position in &mut positions_q {
+        position.0 += offset;
+    }
+
+    // Moving the spawn points must not put the car back on them
+    for (_, transform) in &mut spawn_points.bypass_change_detection().points {
+        transform.translation += offset;
+    }
+    if let Some(mut endless_road) = endless_road {
+        endless_road.shift(offset);
+    }
+    if let Some(mut road_editor) = road_editor {
+        road_editor.shift(offset);
+    }
+
+    for mut transition in &mut transitions_q {
+        transition.shift(offset);
+    }
+    if let Ok(camera) = camera_q.get_single() {
+        let car_transform = *roots_q.get(car).unwrap();
+        let camera_transform = *roots_q.get(camera).unwrap();
+        if let Ok(mut rig) = rigs_q.get_mut(camera) {
+            shift_rig(*mode, &mut rig, offset, &car_transform, &camera_transform);
+        }
+    }
+
+    info!("moved the world by {offset} to keep the car near the origin");
+}
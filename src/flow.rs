@@ -0,0 +1,381 @@
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::car_input::{CarControls, Control};
+use crate::hud::{format_lap_time, LapTimer};
+use crate::map_registry::{MapRegistry, SelectedMap};
+use crate::save::{GraphicsSettings, SaveFile};
+use crate::score::{EndlessScore, GameOver};
+use crate::tuning::{list_presets, preset_path, TuningPreset};
+use crate::{GameState, PlayerCar};
+
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.15);
+const HOVERED_BUTTON_COLOR: Color = Color::rgb(0.3, 0.3, 0.3);
+const PRESSED_BUTTON_COLOR: Color = Color::ORANGE_RED;
+
+/// The tuning preset to race with, the default car when there is none.
+#[derive(Resource, Default)]
+pub struct SelectedCar(pub Option<String>);
+
+/// The control waiting for the next key pressed in the settings menu.
+#[derive(Resource, Default)]
+pub struct Rebinding(Option<Control>);
+
+/// How long a notice stays on screen.
+const NOTICE_DURATION: Duration = Duration::from_secs(8);
+
+/// Every entity of a menu screen, despawned when leaving its state.
+#[derive(Component)]
+pub struct MenuScreen;
+
+#[derive(Component, Clone)]
+pub enum MenuButton {
+    Race,
+    Settings,
+    Quit,
+    SelectCar(Option<String>),
+    /// The index of the map in the registry.
+    SelectMap(usize),
+    BackToMainMenu,
+    Resume,
+    EndRace,
+    RaceAgain,
+    /// Starts the race even though some assets failed to load.
+    Continue,
+    ToggleShadows,
+    ToggleVsync,
+    ToggleFullscreen,
+    Rebind(Control),
+}
+
+/// A message shown on top of the screen for a few seconds.
+#[derive(Component)]
+pub struct Notice(Timer);
+
+pub fn spawn_notice(commands: &mut Commands, message: impl Into<String>) {
+    commands.spawn((
+        Notice(Timer::new(NOTICE_DURATION, TimerMode::Once)),
+        TextBundle::from_section(
+            message,
+            TextStyle { font_size: 20.0, color: Color::ORANGE_RED, ..default() },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(10.0),
+            left: Val::Px(10.0),
+            ..default()
+        })
+        .with_background_color(Color::rgba(0.0, 0.0, 0.0, 0.75)),
+    ));
+}
+
+pub fn expire_notices(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut notices_q: Query<(Entity, &mut Notice)>,
+) {
+    for (entity, mut notice) in &mut notices_q {
+        if notice.0.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub fn despawn_with<T: Component>(mut commands: Commands, query: Query<Entity, With<T>>) {
+    for entity in &query {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+pub fn pause_physics(mut physics_time: ResMut<Time<Physics>>) {
+    physics_time.pause();
+}
+
+pub fn unpause_physics(mut physics_time: ResMut<Time<Physics>>) {
+    physics_time.unpause();
+}
+
+/// Pauses and resumes the race with Escape.
+pub fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    state: Res<State<GameState>>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    if keys.just_pressed(KeyCode::Escape) {
+        match state.get() {
+            GameState::Racing => next_state.set(GameState::Paused),
+            GameState::Paused => next_state.set(GameState::Racing),
+            _ => (),
+        }
+    }
+}
+
+/// Applies the selected tuning preset to the freshly spawned car.
+pub fn apply_selected_car(world: &mut World) {
+    let Some(name) = world.resource::<SelectedCar>().0.clone() else { return };
+    let Ok(car) = world.query_filtered::<Entity, With<PlayerCar>>().get_single(world) else {
+        return;
+    };
+
+    let path = preset_path(&name);
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let preset = TuningPreset::load(&path, &registry.read());
+    match preset.and_then(|preset| preset.apply(world, car)) {
+        Ok(()) => info!("racing with {}", path.display()),
+        Err(e) => error!("could not apply the car definition at {}: {e}", path.display()),
+    }
+}
+
+pub fn handle_menu_buttons(
+    mut selected_car: ResMut<SelectedCar>,
+    mut selected_map: ResMut<SelectedMap>,
+    mut graphics: ResMut<GraphicsSettings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut app_exit: EventWriter<AppExit>,
+    mut buttons_q: Query<
+        (&Interaction, &MenuButton, &mut BackgroundColor),
+        (Changed<Interaction>, With<Button>),
+    >,
+) {
+    for (interaction, button, mut color) in &mut buttons_q {
+        *color = match interaction {
+            Interaction::Pressed => PRESSED_BUTTON_COLOR,
+            Interaction::Hovered => HOVERED_BUTTON_COLOR,
+            Interaction::None => BUTTON_COLOR,
+        }
+        .into();
+
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+
+        match button {
+            MenuButton::Race => next_state.set(GameState::CarSelect),
+            MenuButton::Settings => next_state.set(GameState::Settings),
+            MenuButton::Quit => app_exit.send(AppExit),
+            MenuButton::SelectCar(preset) => {
+                selected_car.0 = preset.clone();
+                next_state.set(GameState::MapSelect);
+            }
+            MenuButton::SelectMap(index) => {
+                selected_map.0 = *index;
+                next_state.set(GameState::Loading);
+            }
+            MenuButton::BackToMainMenu => next_state.set(GameState::MainMenu),
+            MenuButton::Resume => next_state.set(GameState::Racing),
+            MenuButton::EndRace => next_state.set(GameState::Results),
+            MenuButton::RaceAgain => next_state.set(GameState::Loading),
+            MenuButton::Continue => next_state.set(GameState::Racing),
+            MenuButton::ToggleShadows => graphics.shadows = !graphics.shadows,
+            MenuButton::ToggleVsync => graphics.vsync = !graphics.vsync,
+            MenuButton::ToggleFullscreen => graphics.fullscreen = !graphics.fullscreen,
+            MenuButton::Rebind(control) => rebinding.0 = Some(*control),
+        }
+    }
+}
+
+/// Spawns a centered column, with its own camera when there is no race to draw on.
+pub fn spawn_screen(
+    commands: &mut Commands,
+    with_camera: bool,
+    title: &str,
+    content: impl FnOnce(&mut ChildBuilder),
+) {
+    if with_camera {
+        commands.spawn((MenuScreen, Camera2dBundle::default()));
+    }
+
+    commands
+        .spawn((
+            MenuScreen,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    justify_content: JustifyContent::Center,
+                    align_items: AlignItems::Center,
+                    row_gap: Val::Px(12.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                z_index: ZIndex::Global(10),
+                ..default()
+            },
+        ))
+        .with_children(|screen| {
+            screen.spawn(TextBundle::from_section(
+                title,
+                TextStyle { font_size: 60.0, color: Color::WHITE, ..default() },
+            ));
+            content(screen);
+        });
+}
+
+pub fn spawn_button(parent: &mut ChildBuilder, label: &str, button: MenuButton) {
+    parent
+        .spawn((
+            ButtonBundle {
+                style: Style {
+                    width: Val::Px(260.0),
+                    padding: UiRect::all(Val::Px(10.0)),
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                background_color: BUTTON_COLOR.into(),
+                ..default()
+            },
+            button,
+        ))
+        .with_children(|button| {
+            button.spawn(TextBundle::from_section(
+                label,
+                TextStyle { font_size: 28.0, color: Color::WHITE, ..default() },
+            ));
+        });
+}
+
+fn spawn_label(parent: &mut ChildBuilder, text: impl Into<String>) {
+    parent.spawn(TextBundle::from_section(
+        text,
+        TextStyle { font_size: 24.0, color: Color::GRAY, ..default() },
+    ));
+}
+
+pub fn spawn_main_menu(mut commands: Commands) {
+    spawn_screen(&mut commands, true, "Conveyor Belt", |screen| {
+        spawn_button(screen, "Race", MenuButton::Race);
+        spawn_button(screen, "Settings", MenuButton::Settings);
+        spawn_button(screen, "Quit", MenuButton::Quit);
+    });
+}
+
+/// Respawned whenever a setting changes to show its new value.
+pub fn spawn_settings(
+    mut commands: Commands,
+    graphics: Res<GraphicsSettings>,
+    controls: Res<CarControls>,
+    rebinding: Res<Rebinding>,
+) {
+    let on_off = |enabled: bool| if enabled { "on" } else { "off" };
+    spawn_screen(&mut commands, true, "Settings", |screen| {
+        spawn_button(
+            screen,
+            &format!("Shadows {}", on_off(graphics.shadows)),
+            MenuButton::ToggleShadows,
+        );
+        spawn_button(screen, &format!("Vsync {}", on_off(graphics.vsync)), MenuButton::ToggleVsync);
+        spawn_button(
+            screen,
+            &format!("Fullscreen {}", on_off(graphics.fullscreen)),
+            MenuButton::ToggleFullscreen,
+        );
+        for control in Control::ALL {
+            let key = if rebinding.0 == Some(control) {
+                "press a key".to_string()
+            } else {
+                format!("{:?}", controls.key(control))
+            };
+            spawn_button(
+                screen,
+                &format!("{} {key}", control.label()),
+                MenuButton::Rebind(control),
+            );
+        }
+        spawn_button(screen, "Back", MenuButton::BackToMainMenu);
+    });
+}
+
+pub fn cancel_rebinding(mut rebinding: ResMut<Rebinding>) {
+    rebinding.0 = None;
+}
+
+/// Binds the next key pressed to the control picked in the settings menu, Escape cancels.
+pub fn rebind_control(
+    keys: Res<Input<KeyCode>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut controls: ResMut<CarControls>,
+) {
+    let Some(control) = rebinding.0 else { return };
+    let Some(&key) = keys.get_just_pressed().next() else { return };
+    rebinding.0 = None;
+    if key != KeyCode::Escape {
+        controls.bind(control, key);
+    }
+}
+
+pub fn spawn_car_select(mut commands: Commands, save: Res<SaveFile>) {
+    // The tuning presets are the cars we can choose from, once unlocked
+    let presets = list_presets();
+    spawn_screen(&mut commands, true, "Select a car", |screen| {
+        spawn_button(screen, "Default", MenuButton::SelectCar(None));
+        for preset in presets {
+            if save.data.unlocked_cars.contains(&preset) {
+                spawn_button(screen, &preset, MenuButton::SelectCar(Some(preset.clone())));
+            } else {
+                spawn_label(screen, format!("{preset} (locked)"));
+            }
+        }
+        spawn_button(screen, "Back", MenuButton::BackToMainMenu);
+    });
+}
+
+pub fn spawn_map_select(mut commands: Commands, registry: Res<MapRegistry>) {
+    spawn_screen(&mut commands, true, "Select a map", |screen| {
+        for (index, map) in registry.maps.iter().enumerate() {
+            spawn_button(screen, &map.name, MenuButton::SelectMap(index));
+        }
+        spawn_button(screen, "Back", MenuButton::Race);
+    });
+}
+
+pub fn spawn_pause_menu(mut commands: Commands) {
+    spawn_screen(&mut commands, false, "Paused", |screen| {
+        spawn_button(screen, "Resume", MenuButton::Resume);
+        spawn_button(screen, "End race", MenuButton::EndRace);
+        spawn_button(screen, "Main menu", MenuButton::BackToMainMenu);
+    });
+}
+
+pub fn spawn_results(
+    mut commands: Commands,
+    lap_timer: Res<LapTimer>,
+    score: Option<Res<EndlessScore>>,
+    save: Res<SaveFile>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+) {
+    let map = &registry.maps[selected_map.0].name;
+    let lap_time = |duration: Option<_>| duration.map_or_else(|| "-".to_string(), format_lap_time);
+    spawn_screen(&mut commands, false, "Results", |screen| {
+        match score {
+            Some(score) => {
+                let reason = match score.game_over {
+                    Some(GameOver::Crash) => "crashed",
+                    Some(GameOver::OffRoad) => "left the road",
+                    None => "stopped",
+                };
+                spawn_label(screen, format!("{reason} with {:.0} points", score.points));
+                if let Some(high_score) = save.data.high_score(map) {
+                    spawn_label(screen, format!("high score {:.0}", high_score.points));
+                }
+            }
+            None => {
+                spawn_label(screen, format!("best lap {}", lap_time(lap_timer.best_lap())));
+                let record = save.data.best_laps.get(map).map(|best| best.time);
+                spawn_label(screen, format!("record {}", lap_time(record)));
+                spawn_label(screen, format!("last lap {}", lap_time(lap_timer.last_lap())));
+                spawn_label(
+                    screen,
+                    format!("current lap {}", format_lap_time(lap_timer.current())),
+                );
+            }
+        }
+        spawn_button(screen, "Race again", MenuButton::RaceAgain);
+        spawn_button(screen, "Main menu", MenuButton::BackToMainMenu);
+    });
+}
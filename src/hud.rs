@@ -0,0 +1,568 @@
+use std::f32::consts::PI;
+
+use bevy::core_pipeline::clear_color::ClearColorConfig;
+use bevy::prelude::*;
+use bevy::render::camera::{RenderTarget, ScalingMode};
+use bevy::render::render_resource::{
+    Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+};
+use bevy::render::view::RenderLayers;
+use bevy::utils::Duration;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::car_damage::CarDamage;
+use crate::car_input::CarInput;
+use crate::telemetry::WheelTelemetry;
+use crate::{PlayerCar, RaceEntity};
+
+/// The upper speed of every gear of the display gearbox, in km/h.
+const GEARS_TOP_SPEED: [f32; 6] = [50.0, 90.0, 135.0, 185.0, 240.0, 300.0];
+const IDLE_RPM: f32 = 900.0;
+const REDLINE_RPM: f32 = 7000.0;
+const SPEEDOMETER_MAX_KMH: f32 = 320.0;
+
+/// The needles sweep from the bottom left to the bottom right of the dials.
+const NEEDLE_MIN_ANGLE: f32 = -0.75 * PI;
+const NEEDLE_MAX_ANGLE: f32 = 0.75 * PI;
+
+/// A wheel sliding sideways faster than that, in m/s, lights the slip indicator.
+const SLIP_LATERAL_VELOCITY: f32 = 2.0;
+
+const DIAL_SIZE: f32 = 180.0;
+const MINIMAP_SIZE: u32 = 256;
+/// The width of the road around the car shown on the minimap, in meters.
+const MINIMAP_AREA: f32 = 150.0;
+/// The render layer of what must only be seen on the minimap.
+const MINIMAP_LAYER: u8 = 1;
+
+#[derive(Reflect, Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeedUnit {
+    #[default]
+    Kmh,
+    Mph,
+}
+
+impl SpeedUnit {
+    fn from_kmh(self, kmh: f32) -> f32 {
+        match self {
+            SpeedUnit::Kmh => kmh,
+            SpeedUnit::Mph => kmh / 1.609_344,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SpeedUnit::Kmh => "km/h",
+            SpeedUnit::Mph => "mph",
+        }
+    }
+}
+
+/// The dashboard, toggled with H, the speed unit is switched with U.
+#[derive(Resource, Reflect, Debug)]
+#[reflect(Resource)]
+pub struct HudSettings {
+    pub visible: bool,
+    pub unit: SpeedUnit,
+}
+
+impl Default for HudSettings {
+    fn default() -> HudSettings {
+        HudSettings { visible: true, unit: SpeedUnit::default() }
+    }
+}
+
+/// Measures the current lap and its splits.
+#[derive(Resource, Default, Debug)]
+pub struct LapTimer {
+    running: bool,
+    current: Duration,
+    splits: Vec<Duration>,
+    last_lap: Option<Duration>,
+    best_lap: Option<Duration>,
+    /// The number of laps completed.
+    laps: usize,
+}
+
+impl LapTimer {
+    /// Restarts the current lap from zero.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.current = Duration::ZERO;
+        self.splits.clear();
+    }
+
+    /// Records the time of the current lap at a checkpoint.
+    pub fn split(&mut self) {
+        if self.running {
+            self.splits.push(self.current);
+        }
+    }
+
+    /// Finishes the current lap and starts the next one.
+    pub fn complete_lap(&mut self) {
+        if !self.running {
+            return;
+        }
+        let lap = self.current;
+        self.last_lap = Some(lap);
+        self.best_lap = Some(self.best_lap.map_or(lap, |best| best.min(lap)));
+        self.laps += 1;
+        self.start();
+    }
+
+    pub fn current(&self) -> Duration {
+        self.current
+    }
+
+    pub fn last_split(&self) -> Option<Duration> {
+        self.splits.last().copied()
+    }
+
+    pub fn last_lap(&self) -> Option<Duration> {
+        self.last_lap
+    }
+
+    pub fn best_lap(&self) -> Option<Duration> {
+        self.best_lap
+    }
+
+    pub fn laps(&self) -> usize {
+        self.laps
+    }
+}
+
+/// Formats a duration as `m:ss.mmm`.
+pub fn format_lap_time(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    format!("{}:{:02}.{:03}", millis / 60_000, millis / 1000 % 60, millis % 1000)
+}
+
+/// What the gearbox would show for this speed, the car itself has no gears.
+fn gear_and_rpm(speed_kmh: f32, input: &CarInput) -> (String, f32) {
+    let rpm_for = |top_speed: f32| {
+        (IDLE_RPM + (REDLINE_RPM - IDLE_RPM) * speed_kmh.abs() / top_speed).min(REDLINE_RPM)
+    };
+
+    if speed_kmh < -1.0 {
+        ("R".to_string(), rpm_for(GEARS_TOP_SPEED[0]))
+    } else if speed_kmh < 1.0 && !input.accelerate {
+        ("N".to_string(), IDLE_RPM)
+    } else {
+        let index = GEARS_TOP_SPEED
+            .iter()
+            .position(|&top_speed| speed_kmh < top_speed)
+            .unwrap_or(GEARS_TOP_SPEED.len() - 1);
+        ((index + 1).to_string(), rpm_for(GEARS_TOP_SPEED[index]))
+    }
+}
+
+fn needle_rotation(progress: f32) -> Quat {
+    let progress = progress.clamp(0.0, 1.0);
+    // The UI y axis points down, positive angles turn the needle clockwise
+    Quat::from_rotation_z(NEEDLE_MIN_ANGLE + (NEEDLE_MAX_ANGLE - NEEDLE_MIN_ANGLE) * progress)
+}
+
+#[derive(Component)]
+pub struct HudRoot;
+
+#[derive(Component)]
+pub enum HudNeedle {
+    Speedometer,
+    Tachometer,
+}
+
+#[derive(Component)]
+pub enum HudText {
+    Speed,
+    SpeedUnit,
+    Gear,
+    Rpm,
+    LapTime,
+    Split,
+    BestLap,
+    Damage,
+}
+
+#[derive(Component)]
+pub enum HudIndicator {
+    RevLimiter,
+    Slip,
+    Airborne,
+}
+
+#[derive(Component)]
+pub struct MinimapCamera;
+
+pub fn spawn_hud(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut lap_timer: ResMut<LapTimer>,
+) {
+    // Every race starts with fresh lap times
+    *lap_timer = LapTimer::default();
+    lap_timer.start();
+
+    // The minimap is rendered from above into an image displayed by the UI
+    let size = Extent3d { width: MINIMAP_SIZE, height: MINIMAP_SIZE, ..default() };
+    let mut minimap = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("minimap"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..default()
+    };
+    minimap.resize(size);
+    let minimap = images.add(minimap);
+
+    commands.spawn((
+        RaceEntity,
+        MinimapCamera,
+        Camera3dBundle {
+            camera: Camera { order: -1, target: RenderTarget::Image(minimap.clone()), ..default() },
+            camera_3d: Camera3d {
+                clear_color: ClearColorConfig::Custom(Color::rgb(0.1, 0.15, 0.1)),
+                ..default()
+            },
+            projection: Projection::Orthographic(OrthographicProjection {
+                scaling_mode: ScalingMode::FixedVertical(MINIMAP_AREA),
+                far: 2000.0,
+                ..default()
+            }),
+            transform: Transform::from_xyz(0.0, 500.0, 0.0).looking_at(Vec3::ZERO, Vec3::NEG_Z),
+            ..default()
+        },
+        UiCameraConfig { show_ui: false },
+        RenderLayers::from_layers(&[0, MINIMAP_LAYER]),
+    ));
+
+    let text = |size: f32, color: Color| TextStyle { font_size: size, color, ..default() };
+    let panel = Color::rgba(0.05, 0.05, 0.05, 0.75);
+
+    commands
+        .spawn((
+            RaceEntity,
+            HudRoot,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    justify_content: JustifyContent::SpaceBetween,
+                    align_items: AlignItems::FlexEnd,
+                    padding: UiRect::all(Val::Px(10.0)),
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|root| {
+            // Dials, gear, speed and driver aids on the bottom left
+            root.spawn(NodeBundle {
+                style: Style {
+                    align_items: AlignItems::FlexEnd,
+                    column_gap: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|dashboard| {
+                spawn_dial(
+                    dashboard,
+                    HudNeedle::Tachometer,
+                    HudText::Rpm,
+                    panel,
+                    text(18.0, Color::WHITE),
+                );
+                dashboard
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::Center,
+                            padding: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        },
+                        background_color: panel.into(),
+                        ..default()
+                    })
+                    .with_children(|center| {
+                        center.spawn((
+                            TextBundle::from_section("N", text(48.0, Color::ORANGE_RED)),
+                            HudText::Gear,
+                        ));
+                        center.spawn((
+                            TextBundle::from_section("0", text(30.0, Color::WHITE)),
+                            HudText::Speed,
+                        ));
+                        center.spawn((
+                            TextBundle::from_section("km/h", text(16.0, Color::GRAY)),
+                            HudText::SpeedUnit,
+                        ));
+                        center
+                            .spawn(NodeBundle {
+                                style: Style { column_gap: Val::Px(6.0), ..default() },
+                                ..default()
+                            })
+                            .with_children(|indicators| {
+                                for (indicator, label) in [
+                                    (HudIndicator::RevLimiter, "REV"),
+                                    (HudIndicator::Slip, "SLIP"),
+                                    (HudIndicator::Airborne, "AIR"),
+                                ] {
+                                    indicators.spawn((
+                                        TextBundle::from_section(
+                                            label,
+                                            text(14.0, Color::DARK_GRAY),
+                                        ),
+                                        indicator,
+                                    ));
+                                }
+                            });
+                    });
+                spawn_dial(
+                    dashboard,
+                    HudNeedle::Speedometer,
+                    HudText::SpeedUnit,
+                    panel,
+                    text(18.0, Color::WHITE),
+                );
+            });
+
+            // Lap timer and minimap on the right
+            root.spawn(NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::FlexEnd,
+                    row_gap: Val::Px(10.0),
+                    ..default()
+                },
+                ..default()
+            })
+            .with_children(|right| {
+                right
+                    .spawn(NodeBundle {
+                        style: Style {
+                            flex_direction: FlexDirection::Column,
+                            align_items: AlignItems::FlexEnd,
+                            padding: UiRect::all(Val::Px(8.0)),
+                            ..default()
+                        },
+                        background_color: panel.into(),
+                        ..default()
+                    })
+                    .with_children(|timer| {
+                        for (hud_text, size) in [
+                            (HudText::LapTime, 28.0),
+                            (HudText::Split, 16.0),
+                            (HudText::BestLap, 16.0),
+                            (HudText::Damage, 16.0),
+                        ] {
+                            timer.spawn((
+                                TextBundle::from_section("", text(size, Color::WHITE)),
+                                hud_text,
+                            ));
+                        }
+                    });
+                right.spawn(ImageBundle {
+                    style: Style {
+                        width: Val::Px(MINIMAP_SIZE as f32),
+                        height: Val::Px(MINIMAP_SIZE as f32),
+                        border: UiRect::all(Val::Px(2.0)),
+                        ..default()
+                    },
+                    image: UiImage::new(minimap),
+                    background_color: Color::WHITE.into(),
+                    ..default()
+                });
+            });
+        });
+}
+
+/// Spawns a dial with a needle turning around its center and a text under it.
+fn spawn_dial(
+    parent: &mut ChildBuilder,
+    needle: HudNeedle,
+    label: HudText,
+    background: Color,
+    style: TextStyle,
+) {
+    parent
+        .spawn(NodeBundle {
+            style: Style {
+                width: Val::Px(DIAL_SIZE),
+                height: Val::Px(DIAL_SIZE),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::FlexEnd,
+                padding: UiRect::bottom(Val::Px(20.0)),
+                ..default()
+            },
+            background_color: background.into(),
+            ..default()
+        })
+        .with_children(|dial| {
+            // This node covers the whole dial so that it rotates around its center
+            dial.spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    transform: Transform::from_rotation(needle_rotation(0.0)),
+                    ..default()
+                },
+                needle,
+            ))
+            .with_children(|pivot| {
+                pivot.spawn(NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        left: Val::Percent(49.0),
+                        top: Val::Percent(10.0),
+                        width: Val::Percent(2.0),
+                        height: Val::Percent(40.0),
+                        ..default()
+                    },
+                    background_color: Color::ORANGE_RED.into(),
+                    ..default()
+                });
+            });
+            dial.spawn((TextBundle::from_section("", style), label));
+        });
+}
+
+/// Shows the car on the minimap, where the main camera doesn't see it.
+pub fn spawn_minimap_marker(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    car_q: Query<Entity, Added<PlayerCar>>,
+) {
+    for car in &car_q {
+        let marker = commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(shape::Box::new(4.0, 1.0, 8.0).into()),
+                    material: materials.add(StandardMaterial {
+                        base_color: Color::ORANGE_RED,
+                        unlit: true,
+                        ..default()
+                    }),
+                    transform: Transform::from_xyz(0.0, 20.0, 0.0),
+                    ..default()
+                },
+                RenderLayers::layer(MINIMAP_LAYER),
+            ))
+            .id();
+        commands.entity(car).add_child(marker);
+    }
+}
+
+pub fn follow_car_with_minimap(
+    mut camera_q: Query<&mut Transform, (With<MinimapCamera>, Without<PlayerCar>)>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    let Ok(mut camera_transform) = camera_q.get_single_mut() else { return };
+    let Ok(car_transform) = car_q.get_single() else { return };
+
+    // The road ahead of the car is always at the top of the minimap
+    let mut forward = car_transform.forward();
+    forward.y = 0.0;
+    let target = car_transform.translation;
+    *camera_transform = Transform::from_translation(target + Vec3::Y * 500.0)
+        .looking_at(target, forward.try_normalize().unwrap_or(Vec3::NEG_Z));
+}
+
+pub fn toggle_hud(keys: Res<Input<KeyCode>>, mut settings: ResMut<HudSettings>) {
+    if keys.just_pressed(KeyCode::H) {
+        settings.visible = !settings.visible;
+    }
+    if keys.just_pressed(KeyCode::U) {
+        settings.unit = match settings.unit {
+            SpeedUnit::Kmh => SpeedUnit::Mph,
+            SpeedUnit::Mph => SpeedUnit::Kmh,
+        };
+    }
+}
+
+pub fn tick_lap_timer(time: Res<Time>, mut lap_timer: ResMut<LapTimer>) {
+    if lap_timer.running {
+        lap_timer.current += time.delta();
+    }
+}
+
+pub fn update_hud(
+    settings: Res<HudSettings>,
+    lap_timer: Res<LapTimer>,
+    car_q: Query<(Entity, &LinearVelocity, &Transform, &CarInput, &CarDamage), With<PlayerCar>>,
+    wheels_q: Query<(&Parent, &WheelTelemetry)>,
+    mut root_q: Query<&mut Visibility, With<HudRoot>>,
+    mut needles_q: Query<(&HudNeedle, &mut Transform), Without<PlayerCar>>,
+    mut texts_q: Query<(&HudText, &mut Text)>,
+    mut indicators_q: Query<(&HudIndicator, &mut Text), Without<HudText>>,
+) {
+    let visibility = if settings.visible { Visibility::Inherited } else { Visibility::Hidden };
+    for mut root_visibility in &mut root_q {
+        root_visibility.set_if_neq(visibility);
+    }
+    if !settings.visible {
+        return;
+    }
+
+    let Ok((car, &LinearVelocity(lin_vel), car_transform, input, damage)) = car_q.get_single()
+    else {
+        return;
+    };
+    // Negative when driving backward
+    let speed_kmh = car_transform.forward().dot(lin_vel) * 3.6;
+    let (gear, rpm) = gear_and_rpm(speed_kmh, input);
+
+    for (needle, mut transform) in &mut needles_q {
+        let progress = match needle {
+            HudNeedle::Speedometer => speed_kmh.abs() / SPEEDOMETER_MAX_KMH,
+            HudNeedle::Tachometer => rpm / REDLINE_RPM,
+        };
+        transform.rotation = needle_rotation(progress);
+    }
+
+    let lap_time = |label: &str, duration: Option<Duration>| {
+        format!("{label} {}", duration.map_or_else(|| "-:--.---".to_string(), format_lap_time))
+    };
+    for (hud_text, mut text) in &mut texts_q {
+        text.sections[0].value = match hud_text {
+            HudText::Speed => format!("{:.0}", settings.unit.from_kmh(speed_kmh)),
+            HudText::SpeedUnit => settings.unit.label().to_string(),
+            HudText::Gear => gear.clone(),
+            HudText::Rpm => format!("{:.0} rpm", rpm),
+            HudText::LapTime => format_lap_time(lap_timer.current()),
+            HudText::Split => lap_time("split", lap_timer.last_split()),
+            HudText::BestLap => lap_time("best", lap_timer.best_lap().or(lap_timer.last_lap())),
+            HudText::Damage => format!("damage {:.0}%", damage.worst() * 100.0),
+        };
+    }
+
+    let wheels: Vec<_> =
+        wheels_q.iter().filter(|(parent, _)| parent.get() == car).map(|(_, wheel)| wheel).collect();
+    for (indicator, mut text) in &mut indicators_q {
+        let active = match indicator {
+            HudIndicator::RevLimiter => input.accelerate && rpm >= REDLINE_RPM * 0.97,
+            HudIndicator::Slip => wheels.iter().any(|wheel| {
+                wheel.compression > 0.0 && wheel.lateral_velocity.abs() > SLIP_LATERAL_VELOCITY
+            }),
+            HudIndicator::Airborne => {
+                !wheels.is_empty() && wheels.iter().all(|wheel| wheel.compression <= 0.0)
+            }
+        };
+        text.sections[0].style.color = if active { Color::ORANGE_RED } else { Color::DARK_GRAY };
+    }
+}
@@ -0,0 +1,314 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::asset::{LoadState, RecursiveDependencyLoadState, UntypedAssetId};
+use bevy::gltf::Gltf;
+use bevy::prelude::*;
+
+use crate::flow::{spawn_button, spawn_screen, MenuButton};
+use crate::map::fallback_map_scene;
+use crate::map_registry::{MapRegistry, SelectedMap};
+use crate::{GameState, MyAssets};
+
+const PORSCHE_PATH: &str = "cars/models/porsche_911_930_turbo.glb";
+const DIFFUSE_MAP_PATH: &str = "environments_maps/diffuse_rgb9e5_zstd.ktx2";
+const SPECULAR_MAP_PATH: &str = "environments_maps/specular_rgb9e5_zstd.ktx2";
+
+const PROGRESS_BAR_WIDTH: f32 = 400.0;
+
+/// The assets being loaded for the next race.
+///
+/// The root glTF files are tracked instead of their labeled scenes or meshes as only
+/// the root asset is told when its file is missing or invalid.
+#[derive(Resource)]
+pub struct AssetsLoading {
+    porsche: Handle<Gltf>,
+    /// The scene of the map and its path, the endless road has none.
+    map: Option<(String, Handle<Gltf>)>,
+    map_scale: f32,
+    diffuse_map: Handle<Image>,
+    specular_map: Handle<Image>,
+    /// The race assets have been built and the loading only waits for the player.
+    finished: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetStatus {
+    Loading,
+    Loaded,
+    /// The asset itself is loaded but some of its textures or other dependencies are not.
+    Incomplete,
+    Failed,
+}
+
+impl AssetStatus {
+    fn is_settled(self) -> bool {
+        self != AssetStatus::Loading
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            AssetStatus::Loading => "loading",
+            AssetStatus::Loaded => "loaded",
+            AssetStatus::Incomplete => "loaded with missing dependencies",
+            AssetStatus::Failed => "failed, using a fallback",
+        }
+    }
+}
+
+/// The problems met while loading the last race, kept to not report them twice.
+#[derive(Resource, Default)]
+pub struct LoadingReport {
+    pub failures: Vec<String>,
+    /// Waits for the player to acknowledge the failures before starting the race.
+    pub confirm_failures: bool,
+}
+
+impl AssetsLoading {
+    fn tracked(&self) -> Vec<(&str, UntypedAssetId)> {
+        let mut tracked = vec![(PORSCHE_PATH, self.porsche.id().untyped())];
+        if let Some((path, map)) = &self.map {
+            tracked.push((path, map.id().untyped()));
+        }
+        tracked.push((DIFFUSE_MAP_PATH, self.diffuse_map.id().untyped()));
+        tracked.push((SPECULAR_MAP_PATH, self.specular_map.id().untyped()));
+        tracked
+    }
+
+    fn statuses(&self, asset_server: &AssetServer) -> Vec<(String, AssetStatus)> {
+        self.tracked()
+            .into_iter()
+            .map(|(path, id)| (path.to_string(), asset_status(asset_server, id)))
+            .collect()
+    }
+}
+
+fn asset_status(asset_server: &AssetServer, id: impl Into<UntypedAssetId>) -> AssetStatus {
+    match asset_server.get_load_states(id) {
+        None | Some((LoadState::NotLoaded | LoadState::Loading, ..)) => AssetStatus::Loading,
+        Some((LoadState::Failed, ..)) => AssetStatus::Failed,
+        Some((LoadState::Loaded, _, RecursiveDependencyLoadState::Loaded)) => AssetStatus::Loaded,
+        Some((LoadState::Loaded, _, RecursiveDependencyLoadState::Failed)) => {
+            AssetStatus::Incomplete
+        }
+        Some((LoadState::Loaded, ..)) => AssetStatus::Loading,
+    }
+}
+
+pub fn start_loading(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+) {
+    let map = &registry.maps[selected_map.0];
+    commands.insert_resource(AssetsLoading {
+        porsche: asset_server.load(PORSCHE_PATH),
+        map: map.scene.as_ref().map(|scene| (scene.clone(), asset_server.load(scene))),
+        map_scale: map.scale,
+        diffuse_map: asset_server.load(DIFFUSE_MAP_PATH),
+        specular_map: asset_server.load(SPECULAR_MAP_PATH),
+        finished: false,
+    });
+}
+
+/// Builds the race assets, with fallbacks for the missing ones, once everything is settled.
+pub fn finish_loading(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut loading: ResMut<AssetsLoading>,
+    mut report: ResMut<LoadingReport>,
+    mut next_state: ResMut<NextState<GameState>>,
+    gltfs: Res<Assets<Gltf>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut scenes: ResMut<Assets<Scene>>,
+) {
+    if loading.finished {
+        return;
+    }
+
+    let statuses = loading.statuses(&asset_server);
+    if !statuses.iter().all(|(_, status)| status.is_settled()) {
+        return;
+    }
+    loading.finished = true;
+    let porsche_status = asset_status(&asset_server, loading.porsche.id());
+    let diffuse_status = asset_status(&asset_server, loading.diffuse_map.id());
+    let specular_status = asset_status(&asset_server, loading.specular_map.id());
+
+    let mut failures = Vec::new();
+    for (path, status) in statuses {
+        if status != AssetStatus::Loaded {
+            failures.push(format!("{path}: {}", status.description()));
+        }
+    }
+
+    let porsche = gltfs
+        .get(&loading.porsche)
+        .and_then(|gltf| gltf.default_scene.clone().or_else(|| gltf.scenes.first().cloned()));
+    let porsche = porsche.unwrap_or_else(|| {
+        if porsche_status != AssetStatus::Failed {
+            failures.push(format!("{PORSCHE_PATH}: no scene, using a fallback"));
+        }
+        scenes.add(fallback_car_scene(&mut meshes, &mut materials))
+    });
+
+    let map = loading.map.as_ref().map(|(path, map)| {
+        let scene = gltfs
+            .get(map)
+            .and_then(|gltf| gltf.default_scene.clone().or_else(|| gltf.scenes.first().cloned()));
+        scene.unwrap_or_else(|| {
+            if asset_status(&asset_server, map.id()) != AssetStatus::Failed {
+                failures.push(format!("{path}: no scene, using a fallback"));
+            }
+            scenes.add(fallback_map_scene(loading.map_scale, &mut meshes, &mut materials))
+        })
+    });
+
+    // The environment map needs both of its cubemaps, the ambient light replaces it otherwise
+    let environment_map = match (diffuse_status, specular_status) {
+        (AssetStatus::Failed, _) | (_, AssetStatus::Failed) => None,
+        _ => Some(EnvironmentMapLight {
+            diffuse_map: loading.diffuse_map.clone(),
+            specular_map: loading.specular_map.clone(),
+        }),
+    };
+
+    commands.insert_resource(MyAssets { porsche, map, environment_map });
+
+    for failure in &failures {
+        error!("{failure}");
+    }
+
+    let already_reported = report.failures == failures;
+    report.failures = failures;
+    if report.failures.is_empty() || already_reported || !report.confirm_failures {
+        next_state.set(GameState::Racing);
+    }
+}
+
+/// A car body and four named wheels, enough for the car scene hook to find the wheels.
+fn fallback_car_scene(
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Scene {
+    let body_mesh = meshes.add(shape::Box::new(1.8, 0.7, 4.2).into());
+    let body_material = materials.add(Color::ORANGE_RED.into());
+    let wheel_mesh = meshes
+        .add(shape::Cylinder { radius: 0.35, height: 0.25, resolution: 16, segments: 1 }.into());
+    let wheel_material = materials.add(Color::DARK_GRAY.into());
+
+    let mut world = World::new();
+    world.spawn(PbrBundle {
+        mesh: body_mesh,
+        material: body_material,
+        transform: Transform::from_xyz(0.0, 0.9, 0.3),
+        ..default()
+    });
+
+    // The car scene is flipped on the X and Z axes, its front is toward +Z
+    for (name, x, z) in [
+        ("Front-Left-Wheel", 0.95, 1.6),
+        ("Front-Right-Wheel", -0.95, 1.6),
+        ("Back-Left-Wheel", 0.95, -1.0),
+        ("Back-Right-Wheel", -0.95, -1.0),
+    ] {
+        world
+            .spawn((
+                Name::new(name),
+                SpatialBundle::from_transform(Transform::from_xyz(x, 0.35, z)),
+            ))
+            .with_children(|wheel| {
+                wheel.spawn(PbrBundle {
+                    mesh: wheel_mesh.clone(),
+                    material: wheel_material.clone(),
+                    transform: Transform::from_rotation(Quat::from_rotation_z(FRAC_PI_2)),
+                    ..default()
+                });
+            });
+    }
+
+    Scene::new(world)
+}
+
+#[derive(Component)]
+pub struct LoadingAssetText(usize);
+
+#[derive(Component)]
+pub struct LoadingProgressBar;
+
+pub fn spawn_loading_screen(mut commands: Commands) {
+    spawn_screen(&mut commands, true, "Loading...", |screen| {
+        screen
+            .spawn(NodeBundle {
+                style: Style {
+                    width: Val::Px(PROGRESS_BAR_WIDTH),
+                    height: Val::Px(12.0),
+                    ..default()
+                },
+                background_color: Color::DARK_GRAY.into(),
+                ..default()
+            })
+            .with_children(|bar| {
+                bar.spawn((
+                    NodeBundle {
+                        style: Style { width: Val::Percent(0.0), ..default() },
+                        background_color: Color::ORANGE_RED.into(),
+                        ..default()
+                    },
+                    LoadingProgressBar,
+                ));
+            });
+
+        // The car, the map and the two cubemaps at most
+        for i in 0..4 {
+            screen.spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle { font_size: 20.0, color: Color::GRAY, ..default() },
+                ),
+                LoadingAssetText(i),
+            ));
+        }
+
+        spawn_button(screen, "Continue", MenuButton::Continue);
+    });
+}
+
+pub fn update_loading_screen(
+    asset_server: Res<AssetServer>,
+    loading: Option<Res<AssetsLoading>>,
+    report: Res<LoadingReport>,
+    mut texts_q: Query<(&LoadingAssetText, &mut Text)>,
+    mut bar_q: Query<&mut Style, With<LoadingProgressBar>>,
+    mut buttons_q: Query<(&MenuButton, &mut Visibility)>,
+) {
+    let Some(loading) = loading else { return };
+    let statuses = loading.statuses(&asset_server);
+
+    for (&LoadingAssetText(i), mut text) in &mut texts_q {
+        let Some((path, status)) = statuses.get(i) else {
+            text.sections[0].value.clear();
+            continue;
+        };
+        text.sections[0].value = format!("{path}: {}", status.description());
+        text.sections[0].style.color = match *status {
+            AssetStatus::Loading => Color::GRAY,
+            AssetStatus::Loaded => Color::WHITE,
+            AssetStatus::Incomplete | AssetStatus::Failed => Color::ORANGE_RED,
+        };
+    }
+
+    let settled = statuses.iter().filter(|(_, status)| status.is_settled()).count();
+    for mut style in &mut bar_q {
+        style.width = Val::Percent(settled as f32 / statuses.len() as f32 * 100.0);
+    }
+
+    // Only shown when the race waits for the failures to be acknowledged
+    let waiting = settled == statuses.len() && !report.failures.is_empty();
+    for (button, mut visibility) in &mut buttons_q {
+        if let MenuButton::Continue = button {
+            visibility.set_if_neq(if waiting { Visibility::Inherited } else { Visibility::Hidden });
+        }
+    }
+}
@@ -1,55 +1,306 @@
 #![allow(clippy::type_complexity)]
 
 use std::iter::once;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
+use benchmark::{
+    apply_benchmark_preset, drive_benchmark, spawn_benchmark_ground, Benchmark, BENCHMARK_TIME_STEP,
+};
+use bevy::app::ScheduleRunnerPlugin;
 use bevy::core_pipeline::bloom::BloomSettings;
 use bevy::core_pipeline::experimental::taa::{TemporalAntiAliasBundle, TemporalAntiAliasPlugin};
 use bevy::core_pipeline::fxaa::Fxaa;
 use bevy::core_pipeline::tonemapping::Tonemapping;
 use bevy::input::common_conditions::input_toggle_active;
 use bevy::prelude::*;
+use bevy::render::settings::WgpuSettings;
 use bevy::render::view::ColorGrading;
+use bevy::render::RenderPlugin;
+use bevy::time::TimeUpdateStrategy;
 use bevy::transform::TransformSystem;
 use bevy::utils::Duration;
-use bevy::window::close_on_esc;
-use bevy_asset_loader::prelude::*;
+use bevy::window::{close_on_esc, ExitCondition};
+use bevy::winit::WinitPlugin;
 use bevy_atmosphere::collection::nishita::Nishita;
 use bevy_atmosphere::model::AtmosphereModel;
 use bevy_atmosphere::plugin::{AtmosphereCamera, AtmospherePlugin};
 use bevy_atmosphere::system_param::AtmosphereMut;
-use bevy_dolly::dolly_type::Rig;
 use bevy_dolly::system::Dolly;
 use bevy_inspector_egui::quick::WorldInspectorPlugin;
 use bevy_scene_hook::{HookPlugin, HookedSceneBundle, SceneHook};
 use bevy_xpbd_3d::prelude::*;
+use camera::{blend_camera_transition, switch_camera_mode, update_camera, CameraMode};
+use camera_effects::{apply_camera_effects, CameraEffectsSettings, CameraEffectsState};
 use car_acceleration::car_acceleration;
+use car_collision::{
+    detect_car_collisions, record_previous_velocities, CarCollision, PreviousVelocity,
+};
+use car_damage::{damage_cars, repair_car, CarDamage};
+use car_input::{update_car_input_from_keyboard, CarControls, CarInput};
 use car_steering::update_car_steering;
 use car_suspension::{update_car_suspension, CarPhysics};
 use car_wheel_control::{
     update_car_wheel_control, update_car_wheel_rotation_speed, update_car_wheels,
 };
+use drift::{score_drifts, track_drifts, Drift, DriftEvent};
+use endless::{extend_endless_road, track_endless_distance, EndlessRoad};
+use floating_origin::{recenter_world, reset_floating_origin, FloatingOrigin};
+use flow::{
+    apply_selected_car, cancel_rebinding, despawn_with, expire_notices, handle_menu_buttons,
+    pause_physics, rebind_control, spawn_car_select, spawn_main_menu, spawn_map_select,
+    spawn_pause_menu, spawn_results, spawn_settings, toggle_pause, unpause_physics, MenuScreen,
+    Rebinding, SelectedCar,
+};
+use hud::{
+    follow_car_with_minimap, spawn_hud, spawn_minimap_marker, tick_lap_timer, toggle_hud,
+    update_hud, HudSettings, LapTimer,
+};
+use loading::{
+    finish_loading, spawn_loading_screen, start_loading, update_loading_screen, LoadingReport,
+};
+use map::{
+    build_map, camera_behind, move_car_to_spawn_point, pass_checkpoints, setup_map, LapProgress,
+    SpawnPoints, Surface,
+};
+use map_registry::{MapRegistry, SelectedMap};
+use opponents::{drive_opponents, reset_flipped_opponents, spawn_opponents, Opponents};
+use photo_mode::{
+    orbit_photo_camera, photo_mode_inactive, photo_mode_panel, reset_photo_mode, toggle_photo_mode,
+    PhotoMode,
+};
+use props::{break_props, despawn_road_props, place_props, Props};
+use racing_line::build_racing_lines;
+use replay::{
+    advance_replay_playback, apply_replay_input, record_replay_frame, reset_physics_overstep,
+    save_replay_on_exit, Replay, ReplayPlayback, ReplayRecorder,
+};
+use road_editor::{
+    draw_road_editor, edit_road_points, preview_road, reset_road_editor, road_editor_inactive,
+    road_editor_panel, toggle_road_editor, RoadEditor,
+};
+use save::{
+    apply_graphics_settings, record_best_lap, record_ghost_frame, record_high_score,
+    report_save_problem, save_directory, start_ghost_recording, store_settings, GhostRecorder,
+    GraphicsSettings, SaveFile,
+};
+use score::{
+    detect_game_over, score_airtime, score_distance, score_near_misses, spawn_score_display,
+    update_score_display, EndlessScore,
+};
+use telemetry::{
+    finish_telemetry_capture_on_exit, record_telemetry_sample, toggle_telemetry_capture,
+    TelemetryRecorder, TelemetrySettings, WheelTelemetry,
+};
+use telemetry_overlay::{
+    draw_telemetry_overlay, sample_telemetry_overlay, toggle_telemetry_overlay, TelemetryOverlay,
+};
+use traffic::{despawn_traffic, drive_traffic, spawn_traffic, Traffic};
+use tuning::{toggle_tuning_panel, tuning_panel, TuningPanel, TuningPreset};
 
+mod benchmark;
+mod camera;
+mod camera_effects;
 mod car_acceleration;
+mod car_collision;
+mod car_damage;
+mod car_input;
 mod car_steering;
 mod car_suspension;
 mod car_wheel_control;
+mod drift;
+mod endless;
+mod floating_origin;
+mod flow;
+mod hud;
+mod loading;
+mod map;
+mod map_registry;
+mod opponents;
+mod photo_mode;
+mod props;
+mod racing_line;
+mod replay;
+mod road;
+mod road_editor;
+mod road_geometry;
+mod save;
+mod score;
+mod telemetry;
+mod telemetry_overlay;
+mod traffic;
+mod tuning;
 
-fn main() {
-    App::new()
-        .add_state::<GameState>()
-        .add_plugins((
-            DefaultPlugins,
-            TemporalAntiAliasPlugin,
-            HookPlugin,
-            PhysicsPlugins::default(),
-            PhysicsDebugPlugin::default(),
-            AtmospherePlugin,
-            WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::I)),
-        ))
-        .add_loading_state(
-            LoadingState::new(GameState::AssetLoading)
-                .continue_to_state(GameState::Next)
-                .load_collection::<MyAssets>(),
+fn main() -> ExitCode {
+    let (command, map_name) = Command::from_args();
+    let mut app = App::new();
+
+    let registry = MapRegistry::load();
+    let selected_map = match map_name {
+        None => registry.default_map(),
+        Some(name) => match registry.find(&name) {
+            Some(index) => SelectedMap(index),
+            None => {
+                let names: Vec<_> = registry.maps.iter().map(|map| map.name.as_str()).collect();
+                eprintln!("unknown map {name}, the maps are: {}", names.join(", "));
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    app.insert_resource(registry).insert_resource(selected_map);
+
+    // Only the players go through the menus
+    if !matches!(command, Command::Play) {
+        app.insert_resource(State::new(GameState::Loading));
+    }
+    app.add_state::<GameState>();
+
+    match command {
+        Command::Verify(_) | Command::Bench(_) => {
+            // Simulate without any window nor GPU, as fast as possible
+            app.add_plugins((
+                DefaultPlugins
+                    .set(RenderPlugin {
+                        render_creation: WgpuSettings { backends: None, ..default() }.into(),
+                    })
+                    .set(WindowPlugin {
+                        primary_window: None,
+                        exit_condition: ExitCondition::DontExit,
+                        ..default()
+                    })
+                    .disable::<WinitPlugin>(),
+                ScheduleRunnerPlugin::run_loop(Duration::ZERO),
+            ));
+        }
+        Command::Play | Command::Record(_) => {
+            app.add_plugins((
+                DefaultPlugins,
+                TemporalAntiAliasPlugin,
+                PhysicsDebugPlugin::default(),
+                AtmospherePlugin,
+                WorldInspectorPlugin::default().run_if(input_toggle_active(true, KeyCode::I)),
+            ))
+            .init_resource::<TelemetryOverlay>()
+            .init_resource::<TuningPanel>()
+            .init_resource::<RoadEditor>()
+            .register_type::<HudSettings>()
+            .init_resource::<HudSettings>()
+            .init_resource::<CarControls>()
+            .add_systems(RACE_START, (spawn_hud, reset_road_editor.before(setup_with_assets)))
+            .add_systems(
+                PostUpdate,
+                (orbit_photo_camera, follow_car_with_minimap)
+                    .after(PhysicsSet::Sync)
+                    .before(TransformSystem::TransformPropagate)
+                    .run_if(in_state(GameState::Racing)),
+            )
+            .add_systems(
+                Update,
+                (
+                    daylight_cycle,
+                    toggle_telemetry_overlay,
+                    sample_telemetry_overlay
+                        .after(update_car_suspension)
+                        .after(update_car_steering)
+                        .after(car_acceleration)
+                        .after(update_car_wheel_control),
+                    draw_telemetry_overlay.after(sample_telemetry_overlay),
+                    toggle_tuning_panel,
+                    tuning_panel.after(toggle_tuning_panel),
+                    toggle_photo_mode.run_if(road_editor_inactive),
+                    photo_mode_panel.after(toggle_photo_mode),
+                    toggle_road_editor.run_if(photo_mode_inactive),
+                    road_editor_panel.after(toggle_road_editor),
+                    edit_road_points.after(road_editor_panel),
+                    preview_road.after(edit_road_points),
+                    draw_road_editor.after(edit_road_points),
+                    spawn_minimap_marker,
+                    toggle_hud,
+                    tick_lap_timer.run_if(simulation_running),
+                    update_hud.after(toggle_hud).after(tick_lap_timer),
+                )
+                    .run_if(in_state(GameState::Racing)),
+            );
+        }
+    }
+
+    app.add_plugins((HookPlugin, PhysicsPlugins::default()))
+        .init_resource::<LoadingReport>()
+        .add_systems(OnEnter(GameState::Loading), start_loading)
+        .add_systems(Update, finish_loading.run_if(in_state(GameState::Loading)))
+        .register_type::<Surface>()
+        .init_resource::<SpawnPoints>()
+        .init_resource::<LapProgress>()
+        .add_systems(
+            Update,
+            (build_map, move_car_to_spawn_point.after(build_map), pass_checkpoints, expire_notices),
+        )
+        .add_event::<CarCollision>()
+        .add_event::<DriftEvent>()
+        .add_systems(
+            Update,
+            track_drifts
+                .after(update_car_steering)
+                .run_if(in_state(GameState::Racing))
+                .run_if(simulation_running),
+        )
+        .add_systems(
+            Update,
+            (
+                detect_car_collisions,
+                record_previous_velocities.after(detect_car_collisions),
+                damage_cars.after(detect_car_collisions),
+            ),
+        )
+        .add_systems(
+            Update,
+            (track_endless_distance, extend_endless_road.after(track_endless_distance))
+                .run_if(in_state(GameState::Racing))
+                .run_if(resource_exists::<EndlessRoad>()),
+        )
+        .add_systems(
+            Update,
+            (
+                score_distance.after(track_endless_distance),
+                score_near_misses.after(detect_car_collisions),
+                score_airtime,
+                score_drifts.after(track_drifts),
+                detect_game_over.after(detect_car_collisions).after(track_endless_distance),
+                spawn_score_display,
+                update_score_display.after(score_distance),
+            )
+                .run_if(in_state(GameState::Racing))
+                .run_if(simulation_running)
+                .run_if(resource_exists::<EndlessScore>()),
+        )
+        .add_systems(
+            Update,
+            (
+                spawn_traffic,
+                despawn_traffic,
+                drive_traffic.before(car_acceleration).before(update_car_wheel_control),
+            )
+                .run_if(in_state(GameState::Racing))
+                .run_if(resource_exists::<Traffic>()),
+        )
+        .add_systems(
+            Update,
+            (
+                build_racing_lines,
+                spawn_opponents.after(build_racing_lines),
+                drive_opponents.before(car_acceleration).before(update_car_wheel_control),
+                reset_flipped_opponents,
+            )
+                .run_if(in_state(GameState::Racing))
+                .run_if(resource_exists::<Opponents>()),
+        )
+        .add_systems(
+            Update,
+            (place_props, break_props.after(detect_car_collisions), despawn_road_props)
+                .run_if(in_state(GameState::Racing))
+                .run_if(resource_exists::<Props>()),
         )
         .insert_resource(Time::new_with(Physics::fixed_hz(144.0)))
         .insert_resource(PhysicsDebugConfig {
@@ -62,54 +313,356 @@ fn main() {
         .insert_resource(Msaa::Off)
         .insert_resource(AmbientLight { brightness: 0.0, ..default() })
         .register_type::<CarPhysics>()
-        .add_systems(OnEnter(GameState::Next), (setup_with_assets, setup_map))
-        .add_systems(Update, close_on_esc)
+        .register_type::<CarDamage>()
+        .register_type::<CameraMode>()
+        .init_resource::<CameraMode>()
+        .register_type::<CameraEffectsSettings>()
+        .init_resource::<CameraEffectsSettings>()
+        .init_resource::<PhotoMode>()
+        .register_type::<CarInput>()
+        .register_type::<WheelTelemetry>()
+        .register_type::<TelemetrySettings>()
+        .init_resource::<TelemetrySettings>()
+        .init_resource::<TelemetryRecorder>()
+        .init_resource::<LapTimer>()
+        .init_resource::<FloatingOrigin>()
+        .add_systems(
+            RACE_START,
+            (setup_with_assets, reset_floating_origin, reset_photo_mode, reset_physics_overstep),
+        )
         .add_systems(
             Update,
             (
-                daylight_cycle,
+                repair_car
+                    .before(update_car_suspension)
+                    .before(update_car_steering)
+                    .before(car_acceleration)
+                    .before(update_car_wheel_control),
                 update_car_suspension,
                 update_car_steering,
                 car_acceleration,
                 update_car_wheel_rotation_speed,
                 update_car_wheel_control.after(update_car_wheel_rotation_speed),
                 update_car_wheels.after(update_car_wheel_control),
-                text_kmh_update_system,
             )
-                .run_if(in_state(GameState::Next)),
+                .run_if(in_state(GameState::Racing))
+                .run_if(simulation_running),
+        )
+        .add_systems(
+            Update,
+            (
+                switch_camera_mode,
+                toggle_telemetry_capture,
+                record_telemetry_sample
+                    .after(update_car_suspension)
+                    .after(update_car_steering)
+                    .after(car_acceleration)
+                    .after(update_car_wheel_control)
+                    .run_if(simulation_running),
+            )
+                .run_if(in_state(GameState::Racing)),
         )
+        .add_systems(Last, finish_telemetry_capture_on_exit)
         .add_systems(
             PostUpdate,
-            (update_camera, Dolly::<MainCamera>::update_active.after(update_camera))
+            (
+                update_camera,
+                Dolly::<MainCamera>::update_active.after(update_camera),
+                blend_camera_transition.after(Dolly::<MainCamera>::update_active),
+                apply_camera_effects.after(blend_camera_transition),
+            )
                 .after(PhysicsSet::Sync)
                 .before(TransformSystem::TransformPropagate)
-                .run_if(in_state(GameState::Next)),
+                .run_if(in_state(GameState::Racing))
+                .run_if(photo_mode_inactive),
         )
-        .run();
+        .add_systems(
+            PostUpdate,
+            recenter_world
+                .after(blend_camera_transition)
+                .before(apply_camera_effects)
+                .after(PhysicsSet::Sync)
+                .before(TransformSystem::TransformPropagate)
+                .run_if(in_state(GameState::Racing)),
+        );
+
+    let diverged = Arc::new(AtomicBool::new(false));
+    match command {
+        Command::Play => {
+            let save = SaveFile::load(save_directory());
+            let settings = save.data.settings.clone();
+            app.init_resource::<SelectedCar>()
+                .insert_resource(LoadingReport { confirm_failures: true, ..default() })
+                .insert_resource(HudSettings { unit: settings.unit, ..default() })
+                .register_type::<GraphicsSettings>()
+                .insert_resource(settings.graphics)
+                .register_type::<CarControls>()
+                .insert_resource(settings.controls)
+                .init_resource::<Rebinding>()
+                .insert_resource(save)
+                .init_resource::<GhostRecorder>()
+                .add_systems(
+                    RACE_START,
+                    (
+                        setup_map,
+                        apply_selected_car.after(setup_with_assets),
+                        apply_graphics_settings.after(setup_with_assets),
+                        start_ghost_recording,
+                    ),
+                )
+                .add_systems(
+                    Update,
+                    (update_car_input_from_keyboard, record_ghost_frame.run_if(simulation_running))
+                        .chain()
+                        .before(repair_car)
+                        .before(car_acceleration)
+                        .before(update_car_wheel_control)
+                        .run_if(in_state(GameState::Racing)),
+                )
+                .add_systems(
+                    Update,
+                    (
+                        record_best_lap
+                            .after(pass_checkpoints)
+                            .run_if(in_state(GameState::Racing))
+                            .run_if(simulation_running),
+                        apply_graphics_settings.run_if(resource_changed::<GraphicsSettings>()),
+                        store_settings.run_if(
+                            resource_changed::<HudSettings>()
+                                .or_else(resource_changed::<GraphicsSettings>())
+                                .or_else(resource_changed::<CarControls>()),
+                        ),
+                    ),
+                )
+                .add_systems(
+                    OnEnter(GameState::MainMenu),
+                    (despawn_with::<RaceEntity>, spawn_main_menu, report_save_problem),
+                )
+                .add_systems(OnEnter(GameState::CarSelect), spawn_car_select)
+                .add_systems(OnEnter(GameState::MapSelect), spawn_map_select)
+                .add_systems(OnEnter(GameState::Settings), spawn_settings)
+                .add_systems(OnExit(GameState::Settings), cancel_rebinding)
+                .add_systems(
+                    Update,
+                    (
+                        rebind_control,
+                        (despawn_with::<MenuScreen>, spawn_settings).chain().run_if(
+                            resource_changed::<GraphicsSettings>()
+                                .or_else(resource_changed::<CarControls>())
+                                .or_else(resource_changed::<Rebinding>()),
+                        ),
+                    )
+                        .chain()
+                        .after(handle_menu_buttons)
+                        .run_if(in_state(GameState::Settings)),
+                )
+                .add_systems(
+                    OnEnter(GameState::Loading),
+                    (despawn_with::<RaceEntity>, spawn_loading_screen),
+                )
+                .add_systems(OnEnter(GameState::Paused), (pause_physics, spawn_pause_menu))
+                .add_systems(OnExit(GameState::Paused), unpause_physics)
+                .add_systems(
+                    OnEnter(GameState::Results),
+                    (pause_physics, record_high_score, spawn_results.after(record_high_score)),
+                )
+                .add_systems(OnExit(GameState::Results), unpause_physics)
+                .add_systems(
+                    Update,
+                    (
+                        handle_menu_buttons,
+                        toggle_pause.run_if(photo_mode_inactive).run_if(road_editor_inactive),
+                        update_loading_screen.run_if(in_state(GameState::Loading)),
+                    ),
+                );
+            for state in [
+                GameState::MainMenu,
+                GameState::CarSelect,
+                GameState::MapSelect,
+                GameState::Settings,
+                GameState::Loading,
+                GameState::Paused,
+                GameState::Results,
+            ] {
+                app.add_systems(OnExit(state), despawn_with::<MenuScreen>);
+            }
+        }
+        Command::Record(path) => {
+            let map = app.world.resource::<MapRegistry>().maps[selected_map.0].name.clone();
+            app.insert_resource(ReplayRecorder::new(path, map))
+                .add_systems(RACE_START, setup_map)
+                .add_systems(
+                    Update,
+                    (
+                        update_car_input_from_keyboard,
+                        record_replay_frame.run_if(simulation_running),
+                    )
+                        .chain()
+                        .before(repair_car)
+                        .before(car_acceleration)
+                        .before(update_car_wheel_control)
+                        .run_if(in_state(GameState::Racing)),
+                )
+                .add_systems(Update, close_on_esc)
+                .add_systems(Last, save_replay_on_exit);
+        }
+        Command::Verify(path) => {
+            let replay = match Replay::load(&path) {
+                Ok(replay) => replay,
+                Err(e) => {
+                    eprintln!("could not read the replay at {}: {e}", path.display());
+                    return ExitCode::FAILURE;
+                }
+            };
+            // Re-simulate on the map and with the car of the replay, whatever `--map` says
+            let Some(map) = app.world.resource::<MapRegistry>().find(&replay.map) else {
+                eprintln!("the replay was driven on an unknown map: {}", replay.map);
+                return ExitCode::FAILURE;
+            };
+            let car = SelectedCar(replay.car.clone());
+            let playback = ReplayPlayback::new(replay, REPLAY_TOLERANCE, diverged.clone());
+            app.insert_resource(SelectedMap(map))
+                .insert_resource(car)
+                .insert_resource(playback.initial_time_strategy())
+                .insert_resource(playback)
+                .add_systems(RACE_START, (setup_map, apply_selected_car.after(setup_with_assets)))
+                .add_systems(
+                    Update,
+                    apply_replay_input
+                        .before(repair_car)
+                        .before(car_acceleration)
+                        .before(update_car_wheel_control)
+                        .run_if(in_state(GameState::Racing)),
+                )
+                .add_systems(Last, advance_replay_playback.run_if(in_state(GameState::Racing)));
+        }
+        Command::Bench(preset_path) => {
+            let preset = match preset_path {
+                Some(path) => {
+                    let registry = app.world.resource::<AppTypeRegistry>().read();
+                    match TuningPreset::load(&path, &registry) {
+                        Ok(preset) => Some(preset),
+                        Err(e) => {
+                            eprintln!(
+                                "could not read the car definition at {}: {e}",
+                                path.display()
+                            );
+                            return ExitCode::FAILURE;
+                        }
+                    }
+                }
+                None => None,
+            };
+            app.insert_resource(Benchmark::new(preset))
+                .insert_resource(TimeUpdateStrategy::ManualDuration(BENCHMARK_TIME_STEP))
+                .add_systems(RACE_START, spawn_benchmark_ground)
+                .add_systems(
+                    Update,
+                    (apply_benchmark_preset, drive_benchmark)
+                        .chain()
+                        .before(repair_car)
+                        .before(car_acceleration)
+                        .before(update_car_wheel_control)
+                        .run_if(in_state(GameState::Racing)),
+                );
+        }
+    }
+
+    app.run();
+
+    if diverged.load(Ordering::SeqCst) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// The car, the map and everything else of a race are spawned when it starts.
+const RACE_START: OnTransition<GameState> =
+    OnTransition { from: GameState::Loading, to: GameState::Racing };
+
+/// A run condition for the systems driving the cars, paused with the physics by the photo
+/// mode and the road editor.
+fn simulation_running(photo_mode: Res<PhotoMode>, road_editor: Option<Res<RoadEditor>>) -> bool {
+    photo_mode_inactive(photo_mode) && road_editor.map_or(true, road_editor_inactive)
 }
 
-#[derive(AssetCollection, Resource)]
+/// Lights the scene when the environment map failed to load.
+const FALLBACK_AMBIENT_BRIGHTNESS: f32 = 0.3;
+
+/// How far, in meters, a verified replay can end from its recorded position.
+const REPLAY_TOLERANCE: f32 = 0.01;
+
+/// What the binary has been asked to do on the command line.
+enum Command {
+    /// Play the game normally.
+    Play,
+    /// Play the game and record the player inputs in the given file.
+    Record(PathBuf),
+    /// Headlessly re-simulate the given replay and report the divergence.
+    Verify(PathBuf),
+    /// Headlessly run the handling scenarios, optionally with a car definition preset.
+    Bench(Option<PathBuf>),
+}
+
+impl Command {
+    /// The command and the name of the map to race on.
+    fn from_args() -> (Command, Option<String>) {
+        let mut args = std::env::args().skip(1).peekable();
+        let map_name = match args.peek().map(String::as_str) {
+            Some("--map") => args.nth(1),
+            _ => None,
+        };
+        let command = match (args.next().as_deref(), args.next()) {
+            (None, _) => Command::Play,
+            (Some("record"), Some(path)) => Command::Record(path.into()),
+            (Some("verify"), Some(path)) => Command::Verify(path.into()),
+            (Some("bench"), path) => Command::Bench(path.map(PathBuf::from)),
+            _ => {
+                eprintln!(
+                    "usage: conveyor-belt [--map <name>] [record <replay.bin> | verify <replay.bin> | bench [preset.ron]]"
+                );
+                std::process::exit(2);
+            }
+        };
+        (command, map_name)
+    }
+}
+
+/// The assets of a race, built by the loading state with fallbacks for the missing ones.
+#[derive(Resource)]
 struct MyAssets {
-    #[asset(path = "cars/models/porsche_911_930_turbo.glb#Scene0")]
     porsche: Handle<Scene>,
-    // #[asset(path = "cars/models/chassis.glb#Mesh0/Primitive0")]
-    // chassis: Handle<Mesh>,
-    #[asset(path = "maps/playground.glb#Scene0")]
-    playground: Handle<Mesh>,
-
-    #[asset(path = "environments_maps/diffuse_rgb9e5_zstd.ktx2")]
-    diffuse_map: Handle<Image>,
-    #[asset(path = "environments_maps/specular_rgb9e5_zstd.ktx2")]
-    specular_map: Handle<Image>,
+    /// Missing when the map is only an endless road.
+    map: Option<Handle<Scene>>,
+    /// Missing when one of the cubemaps failed to load.
+    environment_map: Option<EnvironmentMapLight>,
 }
 
 #[derive(Clone, Eq, PartialEq, Debug, Hash, Default, States)]
 enum GameState {
     #[default]
-    AssetLoading,
-    Next,
+    MainMenu,
+    CarSelect,
+    MapSelect,
+    /// The graphics and the controls.
+    Settings,
+    /// Loads the assets before starting the race.
+    Loading,
+    Racing,
+    /// The race is frozen, physics included.
+    Paused,
+    Results,
 }
 
+/// Despawned with its children when leaving the race.
+#[derive(Component)]
+struct RaceEntity;
+
+/// The car driven by the player, the other cars are driven by the AI.
+#[derive(Component)]
+struct PlayerCar;
+
 /// Associated to a RayCaster to help get the wheel forward direction and other things.
 #[derive(Component)]
 struct RayCastWheelEntity(pub Entity);
@@ -159,17 +712,23 @@ fn daylight_cycle(
 }
 
 /// set up a simple 3D scene
-fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
-    use bevy_dolly::dolly::drivers::*;
-
-    let car_transform = Transform::from_xyz(0.0, 1.6, 0.0);
+fn setup_with_assets(
+    mut commands: Commands,
+    assets: Res<MyAssets>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+    mut ambient_light: ResMut<AmbientLight>,
+) {
+    let car_transform = registry.maps[selected_map.0].default_spawn();
+    let camera_transform = camera_behind(&car_transform);
 
     // camera
-    commands.spawn((
+    let mut camera = commands.spawn((
+        RaceEntity,
         MainCamera,
         AtmosphereCamera::default(),
         Camera3dBundle {
-            transform: Transform::from_xyz(-6.0, 6.0, -6.0).looking_at(Vec3::ZERO, Vec3::Y),
+            transform: camera_transform,
             camera: Camera { hdr: true, order: 1, ..default() },
             color_grading: ColorGrading { exposure: 1.0, ..default() },
             tonemapping: Tonemapping::AcesFitted,
@@ -181,40 +740,24 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
         },
         Fxaa::default(),
         BloomSettings::default(),
-        EnvironmentMapLight {
-            diffuse_map: assets.diffuse_map.clone(),
-            specular_map: assets.specular_map.clone(),
-        },
         TemporalAntiAliasBundle::default(),
         // Already declared by the Rig camera
         // ScreenSpaceAmbientOcclusionBundle::default(),
-        // Look at and follow vehicule
-        Rig::builder()
-            .with(Position::new(car_transform.translation))
-            .with(Rotation::new(car_transform.rotation))
-            .with(Smooth::new_position(2.5))
-            .with(Arm::new(Vec3::new(0.0, 2.5, 8.0)))
-            .with(
-                LookAt::new(car_transform.translation + Vec3::Y)
-                    .tracking_smoothness(2.0)
-                    .tracking_predictive(true),
-            )
-            .build(),
-        // // Look at vehicule from a fixed point
-        // Rig::builder()
-        //     .with(Position::new(Vec3::new(6.0, 6.0, 6.0)))
-        //     .with(Rotation::new(Quat::IDENTITY))
-        //     .with(Smooth::new_position(1.25).predictive(true))
-        //     .with(
-        //         LookAt::new(car_transform.translation + Vec3::Y)
-        //             .tracking_smoothness(1.25)
-        //             .tracking_predictive(true),
-        //     )
-        //     .build(),
+        CameraMode::default().rig(&car_transform, &camera_transform),
+        CameraEffectsState::default(),
     ));
+    match &assets.environment_map {
+        Some(environment_map) => {
+            camera.insert(environment_map.clone());
+            ambient_light.brightness = 0.0;
+        }
+        // Don't leave the shadows completely black
+        None => ambient_light.brightness = FALLBACK_AMBIENT_BRIGHTNESS,
+    }
 
     // light
     commands.spawn((
+        RaceEntity,
         Sun,
         DirectionalLightBundle {
             directional_light: DirectionalLight {
@@ -228,16 +771,27 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
         },
     ));
 
+    let car = spawn_car(&mut commands, &assets.porsche, car_transform);
+    commands.entity(car).insert(PlayerCar);
+}
+
+/// Spawns a car driven by its `CarInput`, the same for the player and the AI.
+fn spawn_car(commands: &mut Commands, porsche: &Handle<Scene>, transform: Transform) -> Entity {
     let chassis_size = Vec3::new(0.95, 0.4, 1.3);
     let max_suspension = 0.7;
     commands
         .spawn((
+            RaceEntity,
             RigidBody::Dynamic,
-            TransformBundle::from(car_transform),
+            TransformBundle::from(transform),
             Collider::cuboid(2.0, 1.0, 4.4),
             // Collider::trimesh_from_mesh(meshes.get(&assets.chassis).unwrap()).unwrap(),
             AngularDamping(3.0),
             Mass(30.0 - 8.8), // there always is 8.8 more ???
+            CarInput::default(),
+            PreviousVelocity::default(),
+            CarDamage::default(),
+            Drift::default(),
             // CenterOfMass(Vec3::new(0.0, -0.3, 0.3)),
             CarPhysics {
                 chassis_size,
@@ -260,7 +814,7 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
             // Spawn Car and Identify car wheels and elements
             parent.spawn(HookedSceneBundle {
                 scene: SceneBundle {
-                    scene: assets.porsche.clone_weak(),
+                    scene: porsche.clone_weak(),
                     transform: Transform::from_xyz(0.0, -1.0, 0.3)
                         .with_scale(Vec3::new(-1.0, 1.0, -1.0)),
                     ..default()
@@ -277,6 +831,7 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
                                 .commands()
                                 .spawn((
                                     RayCastWheelEntity(entity.id()),
+                                    WheelTelemetry::default(),
                                     RayCaster::new(origin, identity_transform.down())
                                         .with_max_time_of_impact(max_suspension)
                                         .with_solidness(true)
@@ -297,6 +852,7 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
                                 .commands()
                                 .spawn((
                                     RayCastWheelEntity(entity.id()),
+                                    WheelTelemetry::default(),
                                     RayCaster::new(origin, identity_transform.down())
                                         .with_max_time_of_impact(max_suspension)
                                         .with_solidness(true)
@@ -317,6 +873,7 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
                                 .commands()
                                 .spawn((
                                     RayCastWheelEntity(entity.id()),
+                                    WheelTelemetry::default(),
                                     RayCaster::new(origin, identity_transform.down())
                                         .with_max_time_of_impact(max_suspension)
                                         .with_solidness(true)
@@ -337,6 +894,7 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
                                 .commands()
                                 .spawn((
                                     RayCastWheelEntity(entity.id()),
+                                    WheelTelemetry::default(),
                                     RayCaster::new(origin, identity_transform.down())
                                         .with_max_time_of_impact(max_suspension)
                                         .with_solidness(true)
@@ -352,75 +910,6 @@ fn setup_with_assets(mut commands: Commands, assets: Res<MyAssets>) {
                     }
                 }),
             });
-        });
-
-    // Current peed of the car in km/h
-    commands.spawn((
-        TextBundle::from_sections([
-            TextSection::from_style(TextStyle {
-                font_size: 30.0,
-                color: Color::ORANGE_RED,
-                ..default()
-            }),
-            TextSection::new(" km/h", TextStyle { font_size: 30.0, ..default() }),
-        ])
-        .with_style(Style {
-            position_type: PositionType::Absolute,
-            bottom: Val::Px(5.0),
-            left: Val::Px(5.0),
-            ..default()
         })
-        .with_background_color(Color::DARK_GRAY.with_a(0.8)),
-        KmhText,
-    ));
-}
-
-#[derive(Component)]
-struct KmhText;
-
-fn text_kmh_update_system(
-    car_q: Query<(&LinearVelocity, &Transform), With<CarPhysics>>,
-    mut query: Query<&mut Text, With<KmhText>>,
-) {
-    let Ok((&LinearVelocity(lin_vel), car_transform)) = car_q.get_single() else { return };
-    let car_speed_ms = car_transform.forward().dot(lin_vel).abs();
-    let car_speed_kmh = car_speed_ms * 3.6;
-
-    for mut text in &mut query {
-        text.sections[0].value = format!("{car_speed_kmh:>3.0}");
-    }
-}
-
-fn update_camera(mut rig_q: Query<&mut Rig>, car_q: Query<&Transform, With<CarPhysics>>) {
-    use bevy_dolly::dolly::drivers::{LookAt, Position, Rotation};
-
-    let mut rig = rig_q.single_mut();
-    let transform = car_q.single();
-    rig.driver_mut::<Position>().position = transform.translation;
-    rig.driver_mut::<Rotation>().rotation = transform.rotation;
-    rig.driver_mut::<LookAt>().target = transform.translation + Vec3::Y;
-}
-
-fn setup_map(
-    mut commands: Commands,
-    assets: Res<MyAssets>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-) {
-    let m = meshes.get(&assets.playground);
-    let mut map_mesh = m.unwrap().clone();
-    Mesh::generate_tangents(&mut map_mesh).unwrap();
-    let collider = Collider::trimesh_from_mesh(m.unwrap()).unwrap();
-
-    commands
-        .spawn((
-            RigidBody::Static,
-            PbrBundle {
-                transform: Transform::from_xyz(0., 0., 0.).with_scale(Vec3::new(5., 5., 5.)),
-                mesh: meshes.add(map_mesh),
-                material: materials.add(Color::ANTIQUE_WHITE.into()),
-                ..default()
-            },
-        ))
-        .insert(collider);
+        .id()
 }
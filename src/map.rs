@@ -0,0 +1,484 @@
+use bevy::ecs::system::EntityCommands;
+use bevy::gltf::GltfExtras;
+use bevy::prelude::*;
+use bevy_scene_hook::{HookedSceneBundle, SceneHook, SceneHooked};
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::camera::CameraMode;
+use crate::endless::EndlessRoad;
+use crate::flow::spawn_notice;
+use crate::hud::LapTimer;
+use crate::map_registry::{MapRegistry, SelectedMap};
+use crate::opponents::Opponents;
+use crate::props::Props;
+use crate::road::{road_path, spawn_road, RoadSpline};
+use crate::score::EndlessScore;
+use crate::traffic::Traffic;
+use crate::{MainCamera, MyAssets, PlayerCar, RaceEntity};
+
+/// The size of the flat ground used when the map can't be driven on.
+const FALLBACK_GROUND_SIZE: f32 = 1000.0;
+
+/// How high above a spawn node of the map scene, or above the road, a car is dropped.
+pub const SPAWN_HEIGHT: f32 = 1.6;
+
+/// The root of the map scene.
+#[derive(Component)]
+pub struct Map;
+
+/// What a node of the map is for, from its name or its glTF extras.
+///
+/// Nodes are named `Spawn...`, `Checkpoint-<index>`, `Collider...` or `Decor...`,
+/// every other mesh is solid and visible. The same can be set with the `spawn`,
+/// `checkpoint`, `collider` and `decorative` custom properties, along with a `surface`.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapNode {
+    Solid,
+    /// Only collides, it isn't rendered.
+    CollisionOnly,
+    /// Only rendered, nothing collides with it.
+    Decorative,
+    SpawnPoint,
+    Checkpoint(usize),
+}
+
+/// The ground the wheels roll on, changes the grip of the tires.
+#[derive(Component, Reflect, Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Surface {
+    #[default]
+    Asphalt,
+    Dirt,
+    Grass,
+    Ice,
+}
+
+impl Surface {
+    /// The factor applied to the grip of the tires.
+    pub fn grip(self) -> f32 {
+        match self {
+            Surface::Asphalt => 1.0,
+            Surface::Dirt => 0.7,
+            Surface::Grass => 0.55,
+            Surface::Ice => 0.2,
+        }
+    }
+}
+
+/// A sensor the car must go through, in order, to complete a lap.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Checkpoint(pub usize);
+
+/// Where the cars can start from, the ones of the map definition first, then the
+/// ones of the map scene ordered by node name.
+#[derive(Resource, Default, Debug)]
+pub struct SpawnPoints {
+    pub points: Vec<(String, Transform)>,
+    /// The name of the spawn point the player starts from, the first one otherwise.
+    pub default: Option<String>,
+}
+
+impl SpawnPoints {
+    pub fn default_spawn(&self) -> Option<Transform> {
+        let named = self.default.as_ref().and_then(|default| {
+            self.points.iter().find(|(name, _)| name == default).map(|(_, t)| *t)
+        });
+        named.or_else(|| self.points.first().map(|(_, t)| *t))
+    }
+}
+
+/// The checkpoint the car must go through next.
+#[derive(Resource, Default, Debug)]
+pub struct LapProgress {
+    next: usize,
+    count: usize,
+}
+
+#[derive(Deserialize, Default)]
+struct NodeExtras {
+    spawn: Option<bool>,
+    checkpoint: Option<usize>,
+    collider: Option<bool>,
+    decorative: Option<bool>,
+    surface: Option<Surface>,
+}
+
+fn map_node(name: &str, extras: &NodeExtras) -> Option<MapNode> {
+    if extras.spawn == Some(true) || name.starts_with("Spawn") {
+        Some(MapNode::SpawnPoint)
+    } else if let Some(index) = extras.checkpoint {
+        Some(MapNode::Checkpoint(index))
+    } else if let Some(index) = name.strip_prefix("Checkpoint-") {
+        // Blender suffixes duplicated names with .001
+        let index = index.split('.').next().unwrap_or(index);
+        match index.parse() {
+            Ok(index) => Some(MapNode::Checkpoint(index)),
+            Err(_) => {
+                warn!("{name} is not a valid checkpoint name, it should end with its index");
+                None
+            }
+        }
+    } else if extras.collider == Some(true) || name.starts_with("Collider") {
+        Some(MapNode::CollisionOnly)
+    } else if extras.decorative == Some(true) || name.starts_with("Decor") {
+        Some(MapNode::Decorative)
+    } else {
+        None
+    }
+}
+
+/// Marks the nodes of the map with what they are for, the meshes are handled once
+/// the whole scene is there.
+fn hook_map_node(entity: &EntityRef, commands: &mut EntityCommands) {
+    let Some(name) = entity.get::<Name>() else { return };
+    let extras = match entity.get::<GltfExtras>() {
+        Some(GltfExtras { value }) => serde_json::from_str(value).unwrap_or_else(|e| {
+            warn!("invalid extras on {name}: {e}");
+            NodeExtras::default()
+        }),
+        None => NodeExtras::default(),
+    };
+
+    if let Some(node) = map_node(name, &extras) {
+        commands.insert(node);
+    }
+    if let Some(surface) = extras.surface {
+        commands.insert(surface);
+    }
+}
+
+/// A flat ground to drive on when the map failed to load, to be spawned with the given scale.
+pub fn fallback_map_scene(
+    scale: f32,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+) -> Scene {
+    let mut world = World::new();
+    world.spawn((
+        Name::new("Fallback-Ground"),
+        PbrBundle {
+            mesh: meshes
+                .add(shape::Plane { size: FALLBACK_GROUND_SIZE / scale, subdivisions: 0 }.into()),
+            material: materials.add(Color::ANTIQUE_WHITE.into()),
+            ..default()
+        },
+    ));
+    Scene::new(world)
+}
+
+pub fn setup_map(
+    mut commands: Commands,
+    assets: Res<MyAssets>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+    mut spawn_points: ResMut<SpawnPoints>,
+    mut lap_progress: ResMut<LapProgress>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let map = &registry.maps[selected_map.0];
+
+    // Forget about the previous map, the scene adds its own spawn points once built
+    spawn_points.points =
+        map.spawn_points.iter().map(|spawn| (spawn.name.clone(), spawn.transform())).collect();
+    spawn_points.default = map.default_spawn.clone();
+    *lap_progress = LapProgress::default();
+
+    match &map.endless {
+        Some(curve) => {
+            commands.insert_resource(EndlessRoad::new(curve.clone(), &map.default_spawn()));
+            commands.insert_resource(EndlessScore::default());
+        }
+        None => {
+            commands.remove_resource::<EndlessRoad>();
+            commands.remove_resource::<EndlessScore>();
+        }
+    }
+    match &map.traffic {
+        Some(traffic) => commands.insert_resource(Traffic::new(traffic)),
+        None => commands.remove_resource::<Traffic>(),
+    }
+    match &map.opponents {
+        Some(opponents) => commands.insert_resource(Opponents::new(opponents)),
+        None => commands.remove_resource::<Opponents>(),
+    }
+    match &map.props {
+        Some(props) => commands.insert_resource(Props::new(props)),
+        None => commands.remove_resource::<Props>(),
+    }
+
+    if let Some(scene) = &assets.map {
+        commands.spawn((
+            RaceEntity,
+            Map,
+            HookedSceneBundle {
+                scene: SceneBundle {
+                    scene: scene.clone(),
+                    transform: Transform::from_scale(Vec3::splat(map.scale)),
+                    ..default()
+                },
+                hook: SceneHook::new(hook_map_node),
+            },
+        ));
+    }
+
+    if let Some(road) = &map.road {
+        let path = road_path(road);
+        let spawned = RoadSpline::load(&path)
+            .and_then(|spline| spawn_road(&mut commands, &mut meshes, &mut materials, &spline));
+        match spawned {
+            Ok(road) => {
+                commands.entity(road).insert(RaceEntity);
+            }
+            Err(e) => {
+                error!("could not build the road at {}: {e}", path.display());
+                spawn_notice(&mut commands, format!("could not build the road {road}: {e}"));
+            }
+        }
+    }
+}
+
+/// What a map node gives to its descendants.
+#[derive(Clone, Copy)]
+struct Inherited {
+    node: MapNode,
+    surface: Surface,
+    transform: GlobalTransform,
+}
+
+/// Builds the colliders, checkpoints and spawn points once the map scene is hooked.
+pub fn build_map(
+    mut commands: Commands,
+    mut spawn_points: ResMut<SpawnPoints>,
+    mut lap_progress: ResMut<LapProgress>,
+    map_q: Query<(Entity, &Transform), (With<Map>, Added<SceneHooked>)>,
+    nodes_q: MapNodesQuery,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    for (map, &map_transform) in &map_q {
+        let mut builder = MapBuilder {
+            meshes: &meshes,
+            nodes_q: &nodes_q,
+            colliders: Vec::new(),
+            errors: Vec::new(),
+            spawn_points: Vec::new(),
+            checkpoints: Vec::new(),
+        };
+        let inherited = Inherited {
+            node: MapNode::Solid,
+            surface: Surface::default(),
+            transform: GlobalTransform::from(map_transform),
+        };
+        if let Ok((Some(children), ..)) = nodes_q.get(map) {
+            for &child in children {
+                builder.visit(child, inherited);
+            }
+        }
+
+        let MapBuilder { colliders, mut errors, spawn_points: mut spawns, mut checkpoints, .. } =
+            builder;
+
+        let mut solid_colliders = 0;
+        for (entity, collider, node, surface) in colliders {
+            let mut entity = commands.entity(entity);
+            match node {
+                MapNode::Solid => {
+                    entity.insert((RigidBody::Static, collider, surface));
+                    solid_colliders += 1;
+                }
+                MapNode::CollisionOnly => {
+                    entity.insert((RigidBody::Static, collider, surface, Visibility::Hidden));
+                    solid_colliders += 1;
+                }
+                MapNode::Checkpoint(index) => {
+                    entity.insert((
+                        RigidBody::Static,
+                        collider,
+                        Sensor,
+                        Checkpoint(index),
+                        Visibility::Hidden,
+                    ));
+                }
+                MapNode::Decorative | MapNode::SpawnPoint => (),
+            }
+        }
+
+        if solid_colliders == 0 {
+            errors.push("the map has nothing to drive on, using a flat ground".to_string());
+            let size = FALLBACK_GROUND_SIZE;
+            commands.spawn((
+                RaceEntity,
+                RigidBody::Static,
+                Collider::cuboid(size, 1.0, size),
+                PbrBundle {
+                    mesh: meshes.add(shape::Box::new(size, 1.0, size).into()),
+                    material: materials.add(Color::ANTIQUE_WHITE.into()),
+                    transform: Transform::from_xyz(0.0, -0.5, 0.0),
+                    ..default()
+                },
+            ));
+        }
+
+        // Changing the spawn points moves the car back to them, only when there are new ones
+        if !spawns.is_empty() {
+            spawns.sort_by(|(a, _), (b, _)| a.cmp(b));
+            spawn_points.points.extend(spawns);
+        }
+
+        checkpoints.sort_unstable();
+        checkpoints.dedup();
+        if checkpoints.iter().enumerate().any(|(i, &index)| i != index) {
+            errors
+                .push(format!("the checkpoints should be numbered from 0, found {checkpoints:?}"));
+        }
+        lap_progress.count = checkpoints.len();
+        lap_progress.next = 1 % checkpoints.len().max(1);
+
+        for error in &errors {
+            error!("{error}");
+        }
+        if !errors.is_empty() {
+            spawn_notice(&mut commands, errors.join("\n"));
+        }
+    }
+}
+
+type MapNodesQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Option<&'static Children>,
+        &'static Transform,
+        Option<&'static Name>,
+        Option<&'static MapNode>,
+        Option<&'static Surface>,
+        Option<&'static Handle<Mesh>>,
+    ),
+>;
+
+/// Walks the map scene and lists what must be added to its entities.
+struct MapBuilder<'a, 'w, 's> {
+    meshes: &'a Assets<Mesh>,
+    nodes_q: &'a MapNodesQuery<'w, 's>,
+    colliders: Vec<(Entity, Collider, MapNode, Surface)>,
+    errors: Vec<String>,
+    spawn_points: Vec<(String, Transform)>,
+    checkpoints: Vec<usize>,
+}
+
+impl MapBuilder<'_, '_, '_> {
+    fn visit(&mut self, entity: Entity, mut inherited: Inherited) {
+        let Ok((children, transform, name, node, surface, mesh)) = self.nodes_q.get(entity) else {
+            return;
+        };
+        let name = name.map_or("an unnamed node", |name| name.as_str());
+
+        inherited.transform = inherited.transform.mul_transform(*transform);
+        if let Some(&surface) = surface {
+            inherited.surface = surface;
+        }
+        if let Some(&node) = node {
+            inherited.node = node;
+            match node {
+                MapNode::SpawnPoint => {
+                    let (_, rotation, translation) =
+                        inherited.transform.to_scale_rotation_translation();
+                    let spawn = Transform::from_translation(translation + Vec3::Y * SPAWN_HEIGHT)
+                        .with_rotation(rotation);
+                    let name = name.strip_prefix("Spawn-").unwrap_or(name);
+                    self.spawn_points.push((name.to_string(), spawn));
+                }
+                MapNode::Checkpoint(index) => self.checkpoints.push(index),
+                _ => (),
+            }
+        }
+
+        if let Some(mesh) = mesh {
+            self.add_mesh(entity, name, mesh, inherited);
+        }
+
+        for &child in children.into_iter().flatten() {
+            self.visit(child, inherited);
+        }
+    }
+
+    fn add_mesh(&mut self, entity: Entity, name: &str, mesh: &Handle<Mesh>, inherited: Inherited) {
+        if matches!(inherited.node, MapNode::Decorative | MapNode::SpawnPoint) {
+            return;
+        }
+
+        let Some(collider) = self.meshes.get(mesh).and_then(Collider::trimesh_from_mesh) else {
+            self.errors.push(format!("could not build the collider of {name}"));
+            return;
+        };
+
+        self.colliders.push((entity, collider, inherited.node, inherited.surface));
+    }
+}
+
+/// Puts the car on the default spawn point of the map, and the camera behind it.
+pub fn move_car_to_spawn_point(
+    mut commands: Commands,
+    spawn_points: Res<SpawnPoints>,
+    mode: Res<CameraMode>,
+    mut car_q: Query<
+        (&mut Transform, &mut Position, &mut Rotation, &mut LinearVelocity, &mut AngularVelocity),
+        With<PlayerCar>,
+    >,
+    mut camera_q: Query<(Entity, &mut Transform), (With<MainCamera>, Without<PlayerCar>)>,
+) {
+    if !spawn_points.is_changed() {
+        return;
+    }
+    let Some(spawn) = spawn_points.default_spawn() else { return };
+
+    if let Ok((camera, mut camera_transform)) = camera_q.get_single_mut() {
+        *camera_transform = camera_behind(&spawn);
+        commands.entity(camera).insert(mode.rig(&spawn, &camera_transform));
+    }
+
+    for (mut transform, mut position, mut rotation, mut lin_vel, mut ang_vel) in &mut car_q {
+        *transform = spawn;
+        position.0 = spawn.translation;
+        rotation.0 = spawn.rotation;
+        lin_vel.0 = Vec3::ZERO;
+        ang_vel.0 = Vec3::ZERO;
+    }
+}
+
+/// Splits the lap at every checkpoint and completes it at the first one.
+pub fn pass_checkpoints(
+    mut collisions: EventReader<CollisionStarted>,
+    mut lap_progress: ResMut<LapProgress>,
+    mut lap_timer: ResMut<LapTimer>,
+    checkpoints_q: Query<&Checkpoint>,
+    car_q: Query<(), With<PlayerCar>>,
+) {
+    for &CollisionStarted(a, b) in collisions.read() {
+        let checkpoint = match (checkpoints_q.get(a), checkpoints_q.get(b)) {
+            (Ok(checkpoint), _) if car_q.contains(b) => checkpoint,
+            (_, Ok(checkpoint)) if car_q.contains(a) => checkpoint,
+            _ => continue,
+        };
+
+        // Checkpoints taken out of order don't count
+        if lap_progress.count == 0 || checkpoint.0 != lap_progress.next {
+            continue;
+        }
+
+        if checkpoint.0 == 0 {
+            lap_timer.complete_lap();
+        } else {
+            lap_timer.split();
+        }
+        lap_progress.next = (lap_progress.next + 1) % lap_progress.count;
+    }
+}
+
+/// Where the camera starts, behind and above the car.
+pub fn camera_behind(car_transform: &Transform) -> Transform {
+    Transform::from_translation(car_transform.transform_point(Vec3::new(0.0, 6.0, 10.0)))
+        .looking_at(car_transform.translation, Vec3::Y)
+}
@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+use serde::Deserialize;
+
+use crate::endless::DifficultyCurve;
+use crate::opponents::OpponentsDefinition;
+use crate::props::PropsDefinition;
+use crate::traffic::TrafficDefinition;
+
+/// The directory, relative to the assets, where the map definitions are.
+const MAPS_DIRECTORY: &str = "maps";
+
+/// The map raced on when none is chosen.
+const DEFAULT_MAP: &str = "Playground";
+
+/// A map that can be raced on, read from a `.ron` file of the maps directory.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MapDefinition {
+    pub name: String,
+    /// The glTF file of the map, relative to the assets.
+    #[serde(default)]
+    pub scene: Option<String>,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Where the cars can start from, more are found in the map scene.
+    #[serde(default)]
+    pub spawn_points: Vec<SpawnDefinition>,
+    /// The name of the spawn point the player starts from, the first one otherwise.
+    #[serde(default)]
+    pub default_spawn: Option<String>,
+    /// The name of a road spline of the roads directory, added to the map scene.
+    #[serde(default)]
+    pub road: Option<String>,
+    /// Makes the map an endless road getting harder with the distance.
+    #[serde(default)]
+    pub endless: Option<DifficultyCurve>,
+    /// The cars driving along the roads of the map.
+    #[serde(default)]
+    pub traffic: Option<TrafficDefinition>,
+    /// The cars racing the player along the road of the map.
+    #[serde(default)]
+    pub opponents: Option<OpponentsDefinition>,
+    /// The props left along the roads of the map.
+    #[serde(default)]
+    pub props: Option<PropsDefinition>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpawnDefinition {
+    pub name: String,
+    pub translation: [f32; 3],
+    /// The heading of the car in degrees, counter-clockwise seen from above.
+    #[serde(default)]
+    pub yaw: f32,
+}
+
+impl SpawnDefinition {
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(Vec3::from(self.translation))
+            .with_rotation(Quat::from_rotation_y(self.yaw.to_radians()))
+    }
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl MapDefinition {
+    /// The map used when no definition can be read.
+    fn playground() -> MapDefinition {
+        MapDefinition {
+            name: "Playground".to_string(),
+            scene: Some("maps/playground.glb".to_string()),
+            scale: 5.0,
+            spawn_points: vec![SpawnDefinition {
+                name: "start".to_string(),
+                translation: [0.0, 1.6, 0.0],
+                yaw: 0.0,
+            }],
+            default_spawn: Some("start".to_string()),
+            road: None,
+            endless: None,
+            traffic: None,
+            opponents: None,
+            props: None,
+        }
+    }
+
+    /// Where the player starts, the named default spawn or the first one.
+    pub fn default_spawn(&self) -> Transform {
+        let named = self
+            .default_spawn
+            .as_ref()
+            .and_then(|default| self.spawn_points.iter().find(|spawn| &spawn.name == default));
+        named.or(self.spawn_points.first()).map_or(Transform::IDENTITY, |spawn| spawn.transform())
+    }
+
+    pub fn load(path: &Path) -> Result<MapDefinition, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut map: MapDefinition = ron::from_str(&text)?;
+        if let Some(curve) = &mut map.endless {
+            if curve.steps.is_empty() {
+                return Err("the difficulty curve of the endless road has no step".into());
+            }
+            curve.steps.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        }
+        Ok(map)
+    }
+}
+
+/// Every map found in the maps directory, sorted by name.
+#[derive(Resource, Debug)]
+pub struct MapRegistry {
+    pub maps: Vec<MapDefinition>,
+}
+
+impl MapRegistry {
+    /// Reads the definitions, the invalid ones are skipped with an error.
+    ///
+    /// It runs before the app and its logger start, the problems go straight to stderr.
+    pub fn load() -> MapRegistry {
+        let directory = FileAssetReader::get_base_path().join("assets").join(MAPS_DIRECTORY);
+        let mut maps: Vec<_> = fs::read_dir(&directory)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+            .filter_map(|path| match MapDefinition::load(&path) {
+                Ok(map) => Some(map),
+                Err(e) => {
+                    eprintln!("invalid map definition at {}: {e}", path.display());
+                    None
+                }
+            })
+            .collect();
+
+        if maps.is_empty() {
+            eprintln!("no map definition in {}, using the playground", directory.display());
+            maps.push(MapDefinition::playground());
+        }
+
+        maps.sort_by(|a, b| a.name.cmp(&b.name));
+        MapRegistry { maps }
+    }
+
+    /// The playground when there is one, the first map otherwise.
+    pub fn default_map(&self) -> SelectedMap {
+        SelectedMap(self.find(DEFAULT_MAP).unwrap_or(0))
+    }
+
+    pub fn find(&self, name: &str) -> Option<usize> {
+        self.maps.iter().position(|map| map.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// The index of the map to race on in the registry.
+#[derive(Resource, Default, Debug, Clone, Copy)]
+pub struct SelectedMap(pub usize);
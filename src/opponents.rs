@@ -0,0 +1,434 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use interpolation::Lerp;
+use serde::Deserialize;
+
+use crate::car_input::CarInput;
+use crate::car_suspension::CarPhysics;
+use crate::map::SPAWN_HEIGHT;
+use crate::racing_line::RacingLine;
+use crate::traffic::steer_toward;
+use crate::{spawn_car, MyAssets, PlayerCar};
+
+/// The sideways acceleration the tires hold with a grip factor of one, in m/s².
+const GRIP_ACCELERATION: f32 = 12.0;
+
+/// How far ahead the corners are braked for.
+const BRAKING_HORIZON: f32 = 250.0;
+
+/// How far ahead a car steers toward, at least and per m/s.
+const LOOK_AHEAD: f32 = 10.0;
+const LOOK_AHEAD_TIME: f32 = 0.5;
+
+/// How far from its target speed a car coasts instead of accelerating or braking.
+const SPEED_TOLERANCE: f32 = 0.5;
+
+/// The distance between two cars of the starting grid.
+const GRID_SPACING: f32 = 12.0;
+
+/// How far ahead the slower cars on the racing line are overtaken, and how far to their side.
+const OVERTAKE_RANGE: f32 = 30.0;
+const OVERTAKE_OFFSET: f32 = 3.5;
+
+/// How far behind a car overtaken must be to go back on the racing line.
+const PASSED_GAP: f32 = 6.0;
+
+/// How fast a car moves to the side of the racing line, in m/s.
+const OVERTAKE_SIDE_SPEED: f32 = 2.0;
+
+/// A car closer than this to the path is in the way.
+const CAR_WIDTH: f32 = 2.2;
+
+/// The gap kept to the car ahead until it can be overtaken, at least and per m/s.
+const MIN_GAP: f32 = 8.0;
+const FOLLOWING_TIME: f32 = 0.5;
+
+/// How far from the direction of the line a car is spun around.
+const SPIN_ANGLE: f32 = PI * 0.6;
+
+/// How slow and for how long a car trying to drive is stuck.
+const STUCK_SPEED: f32 = 1.0;
+const STUCK_TIME: f32 = 2.0;
+
+/// How long a spun or stuck car backs up to face the line again.
+const RECOVERY_TIME: f32 = 1.5;
+
+/// How long a car stays on its roof before being put back on the line.
+const FLIPPED_TIME: f32 = 3.0;
+
+/// How far from the racing line a car can be and still follow it.
+const ON_LINE_DISTANCE: f32 = 30.0;
+
+/// How the opponents drive.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum OpponentDifficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl OpponentDifficulty {
+    /// How much of the grip of its tires a car uses in the corners and under braking.
+    pub fn grip_usage(self) -> f32 {
+        match self {
+            OpponentDifficulty::Easy => 0.7,
+            OpponentDifficulty::Normal => 0.85,
+            OpponentDifficulty::Hard => 0.97,
+        }
+    }
+
+    /// How long, in seconds, the driver takes to act on what it sees.
+    pub fn reaction_delay(self) -> f32 {
+        match self {
+            OpponentDifficulty::Easy => 0.4,
+            OpponentDifficulty::Normal => 0.2,
+            OpponentDifficulty::Hard => 0.08,
+        }
+    }
+}
+
+/// The opponents racing on the road of a map.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OpponentsDefinition {
+    pub cars: usize,
+    #[serde(default)]
+    pub difficulty: OpponentDifficulty,
+}
+
+/// The opponents of the race, put on the grid once the track is built.
+#[derive(Resource)]
+pub struct Opponents {
+    to_spawn: usize,
+    difficulty: OpponentDifficulty,
+}
+
+impl Opponents {
+    pub fn new(definition: &OpponentsDefinition) -> Opponents {
+        Opponents { to_spawn: definition.cars, difficulty: definition.difficulty }
+    }
+}
+
+/// A car racing along the racing line of the track.
+#[derive(Component, Debug)]
+pub struct Opponent {
+    pub difficulty: OpponentDifficulty,
+    /// The inputs decided but not acted on yet, with when they were decided.
+    decisions: VecDeque<(f32, CarInput)>,
+    /// How far to the right of the racing line it drives to overtake.
+    overtake_offset: f32,
+    stuck_since: Option<f32>,
+    recovering_until: Option<f32>,
+    flipped_since: Option<f32>,
+}
+
+impl Opponent {
+    pub fn new(difficulty: OpponentDifficulty) -> Opponent {
+        Opponent {
+            difficulty,
+            decisions: VecDeque::new(),
+            overtake_offset: 0.0,
+            stuck_since: None,
+            recovering_until: None,
+            flipped_since: None,
+        }
+    }
+
+    /// Queues a decision and gives the latest one made at least the reaction delay ago.
+    fn react(&mut self, now: f32, decision: CarInput) -> Option<CarInput> {
+        self.decisions.push_back((now, decision));
+        let mut reaction = None;
+        while let Some(&(decided, decision)) = self.decisions.front() {
+            if now - decided < self.difficulty.reaction_delay() {
+                break;
+            }
+            reaction = Some(decision);
+            self.decisions.pop_front();
+        }
+        reaction
+    }
+}
+
+/// The grip factor of the tires at a speed, like in `update_car_steering`, for the tires
+/// with the least grip.
+fn grip_factor(car_physics: &CarPhysics, speed: f32) -> f32 {
+    let normalized_speed = (speed.abs() / car_physics.top_speed).clamp(0.0, 1.0);
+    let t = normalized_speed * car_physics.tire_grip_velocity_multiplier;
+    let front =
+        car_physics.front_tire_max_grip_factor.lerp(&car_physics.front_tire_min_grip_factor, &t);
+    let back =
+        car_physics.back_tire_max_grip_factor.lerp(&car_physics.back_tire_min_grip_factor, &t);
+    front.min(back).max(0.0)
+}
+
+/// The fastest a car can take a turn of the given curvature.
+fn corner_speed(car_physics: &CarPhysics, grip_usage: f32, curvature: f32) -> f32 {
+    if curvature <= f32::EPSILON {
+        return car_physics.top_speed;
+    }
+    // The grip drops with the speed, converge toward the speed the tires can hold
+    let mut speed = car_physics.top_speed;
+    for _ in 0..4 {
+        let acceleration = GRIP_ACCELERATION * grip_factor(car_physics, speed) * grip_usage;
+        speed = (acceleration / curvature).sqrt().min(car_physics.top_speed);
+    }
+    speed
+}
+
+/// The fastest a car can go and still brake in time for every corner ahead.
+fn braking_speed(
+    line: &RacingLine,
+    from: usize,
+    car_physics: &CarPhysics,
+    speed: f32,
+    grip_usage: f32,
+) -> f32 {
+    let braking = GRIP_ACCELERATION * grip_factor(car_physics, speed) * grip_usage;
+    let (mut index, mut travelled) = (from, 0.0);
+    let mut target = car_physics.top_speed;
+    while travelled < BRAKING_HORIZON {
+        let corner = corner_speed(car_physics, grip_usage, line.points[index].curvature);
+        target = target.min((corner * corner + 2.0 * braking * travelled).sqrt());
+        let Some(next) = line.next(index) else { break };
+        travelled += line.points[index].position.distance(line.points[next].position);
+        index = next;
+    }
+    target
+}
+
+fn find_on_line<'a>(
+    lines_q: &'a Query<(&RacingLine, &Transform)>,
+    position: Vec3,
+) -> Option<(&'a RacingLine, Transform, usize)> {
+    lines_q
+        .iter()
+        .filter_map(|(line, line_transform)| {
+            let local = line_transform.compute_affine().inverse().transform_point3(position);
+            let index = line.nearest(local)?;
+            let distance_squared = line.points[index].position.distance_squared(local);
+            Some((distance_squared, (line, *line_transform, index)))
+        })
+        .filter(|(distance_squared, _)| *distance_squared < ON_LINE_DISTANCE * ON_LINE_DISTANCE)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, found)| found)
+}
+
+/// Drives the opponents along the racing line, as fast as their grip allows, overtaking
+/// the slower cars and backing up after a spin.
+pub fn drive_opponents(
+    time: Res<Time>,
+    mut opponents_q: Query<(
+        Entity,
+        &mut Opponent,
+        &mut CarInput,
+        &CarPhysics,
+        &Transform,
+        &LinearVelocity,
+    )>,
+    cars_q: Query<(Entity, &Transform, &LinearVelocity), With<CarPhysics>>,
+    lines_q: Query<(&RacingLine, &Transform)>,
+) {
+    let now = time.elapsed_seconds();
+    let dt = time.delta_seconds();
+
+    for (entity, mut opponent, mut input, car_physics, transform, &LinearVelocity(lin_vel)) in
+        &mut opponents_q
+    {
+        let Some((line, line_transform, index)) = find_on_line(&lines_q, transform.translation)
+        else {
+            // Off the track, let it roll
+            if let Some(reaction) = opponent.react(now, CarInput::default()) {
+                input.set_if_neq(reaction);
+            }
+            continue;
+        };
+        let point = line.points[index];
+        let line_position = line_transform.transform_point(point.position);
+        let line_forward = line_transform.rotation * point.forward;
+        let line_right = line_transform.rotation * point.right;
+        let speed = transform.forward().dot(lin_vel);
+
+        // Back up with the wheels turned away from the line, which swings the nose toward it
+        let stuck = opponent.stuck_since.map_or(false, |since| now - since > STUCK_TIME);
+        let spun = transform.forward().angle_between(line_forward) > SPIN_ANGLE;
+        if (stuck || spun) && opponent.recovering_until.is_none() {
+            opponent.recovering_until = Some(now + RECOVERY_TIME);
+            opponent.stuck_since = None;
+        }
+        if let Some(until) = opponent.recovering_until {
+            if now < until {
+                let line_on_left = transform.forward().cross(line_forward).y > 0.0;
+                let decision = CarInput {
+                    accelerate: false,
+                    reverse: true,
+                    steer_left: !line_on_left,
+                    steer_right: line_on_left,
+                    ..default()
+                };
+                if let Some(reaction) = opponent.react(now, decision) {
+                    input.set_if_neq(reaction);
+                }
+                continue;
+            }
+            opponent.recovering_until = None;
+        }
+
+        // Where the other cars are, along the line and from it
+        let others: Vec<_> = cars_q
+            .iter()
+            .filter(|&(other, ..)| other != entity)
+            .map(|(_, other_transform, &LinearVelocity(velocity))| {
+                let position = other_transform.translation;
+                let gap = (position - transform.translation).dot(line_forward);
+                (gap, velocity.dot(line_forward), (position - line_position).dot(line_right))
+            })
+            .collect();
+
+        // Pass the slower cars on the line by the side with the most room, until they are behind
+        let overtaking = others.iter().any(|&(gap, other_speed, lateral)| {
+            lateral.abs() < CAR_WIDTH
+                && gap > -PASSED_GAP
+                && gap < OVERTAKE_RANGE
+                && (gap < 0.0 || other_speed < speed)
+        });
+        let side = if opponent.overtake_offset != 0.0 {
+            opponent.overtake_offset.signum()
+        } else if point.offset > 0.0 {
+            -1.0
+        } else {
+            1.0
+        };
+        let target_offset = if overtaking { side * OVERTAKE_OFFSET } else { 0.0 };
+        let target_offset = (point.offset + target_offset)
+            .clamp(-point.half_width, point.half_width)
+            - point.offset;
+        let max_step = OVERTAKE_SIDE_SPEED * dt;
+        opponent.overtake_offset +=
+            (target_offset - opponent.overtake_offset).clamp(-max_step, max_step);
+
+        let grip_usage = opponent.difficulty.grip_usage();
+        let mut target_speed = braking_speed(line, index, car_physics, speed, grip_usage);
+
+        // Wait behind the car in the way until there is room to pass it
+        let in_the_way = others
+            .iter()
+            .filter(|&&(gap, _, lateral)| {
+                gap > 0.0 && (lateral - opponent.overtake_offset).abs() < CAR_WIDTH
+            })
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+        if let Some(&(gap, other_speed, _)) = in_the_way {
+            if gap < MIN_GAP + speed.max(0.0) * FOLLOWING_TIME {
+                target_speed = target_speed.min(other_speed.max(0.0));
+            }
+        }
+
+        let look_ahead = LOOK_AHEAD + speed.max(0.0) * LOOK_AHEAD_TIME;
+        let target_point = line.points[line.index_ahead(index, look_ahead)];
+        let target = line_transform
+            .transform_point(target_point.position + target_point.right * opponent.overtake_offset);
+        let (steer_left, steer_right) = steer_toward(transform, car_physics, target);
+
+        let decision = CarInput {
+            accelerate: speed < target_speed - SPEED_TOLERANCE,
+            reverse: speed > target_speed + SPEED_TOLERANCE,
+            steer_left,
+            steer_right,
+            ..default()
+        };
+        if decision.accelerate && speed.abs() < STUCK_SPEED {
+            opponent.stuck_since.get_or_insert(now);
+        } else {
+            opponent.stuck_since = None;
+        }
+        if let Some(reaction) = opponent.react(now, decision) {
+            input.set_if_neq(reaction);
+        }
+    }
+}
+
+/// Puts the opponents left on their roof back on the racing line.
+pub fn reset_flipped_opponents(
+    time: Res<Time>,
+    mut opponents_q: Query<
+        (
+            &mut Opponent,
+            &mut Transform,
+            &mut Position,
+            &mut Rotation,
+            &mut LinearVelocity,
+            &mut AngularVelocity,
+        ),
+        Without<RacingLine>,
+    >,
+    lines_q: Query<(&RacingLine, &Transform)>,
+) {
+    let now = time.elapsed_seconds();
+    for (mut opponent, mut transform, mut position, mut rotation, mut lin_vel, mut ang_vel) in
+        &mut opponents_q
+    {
+        if transform.up().y > 0.3 {
+            opponent.flipped_since = None;
+            continue;
+        }
+        let since = *opponent.flipped_since.get_or_insert(now);
+        if now - since < FLIPPED_TIME {
+            continue;
+        }
+        let Some((line, line_transform, index)) = find_on_line(&lines_q, transform.translation)
+        else {
+            continue;
+        };
+
+        let point = line.points[index];
+        let forward = line_transform.rotation * point.forward;
+        let translation = line_transform.transform_point(point.position) + Vec3::Y * SPAWN_HEIGHT;
+        *transform = Transform::from_translation(translation).looking_to(forward, Vec3::Y);
+        position.0 = transform.translation;
+        rotation.0 = transform.rotation;
+        lin_vel.0 = Vec3::ZERO;
+        ang_vel.0 = Vec3::ZERO;
+        *opponent = Opponent::new(opponent.difficulty);
+    }
+}
+
+/// Puts the opponents on the starting grid behind the player once the track is built.
+pub fn spawn_opponents(
+    mut commands: Commands,
+    assets: Res<MyAssets>,
+    mut opponents: ResMut<Opponents>,
+    player_q: Query<&Transform, With<PlayerCar>>,
+    lines_q: Query<(&RacingLine, &Transform)>,
+) {
+    if opponents.to_spawn == 0 {
+        return;
+    }
+    let Ok(player_transform) = player_q.get_single() else { return };
+    let Some((line, line_transform, start)) = find_on_line(&lines_q, player_transform.translation)
+    else {
+        return;
+    };
+
+    for slot in 1..=opponents.to_spawn {
+        // Two cars side by side on every row
+        let behind = ((slot + 1) / 2) as f32 * GRID_SPACING;
+        let (index, travelled) = line.index_behind(start, behind);
+        // An open road may start before the back of the grid
+        if travelled < behind {
+            warn!("no room behind the player for {} opponents", opponents.to_spawn - slot + 1);
+            break;
+        }
+        let point = line.points[index];
+        let side = if slot % 2 == 0 { 1.0 } else { -1.0 };
+        let middle = point.position - point.right * point.offset;
+        let position =
+            middle + point.right * side * point.half_width / 2.0 + Vec3::Y * SPAWN_HEIGHT;
+        let transform = Transform::from_translation(line_transform.transform_point(position))
+            .looking_to(line_transform.rotation * point.forward, Vec3::Y);
+        let car = spawn_car(&mut commands, &assets.porsche, transform);
+        commands.entity(car).insert(Opponent::new(opponents.difficulty));
+    }
+    opponents.to_spawn = 0;
+}
@@ -0,0 +1,202 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::core_pipeline::bloom::BloomSettings;
+use bevy::core_pipeline::tonemapping::Tonemapping;
+use bevy::input::mouse::{MouseMotion, MouseWheel};
+use bevy::prelude::*;
+use bevy::render::view::screenshot::ScreenshotManager;
+use bevy::render::view::ColorGrading;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContexts;
+use bevy_inspector_egui::egui;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::camera::CameraTransition;
+use crate::{MainCamera, PlayerCar};
+
+const ORBIT_MOUSE_SENSITIVITY: f32 = 0.005;
+const ORBIT_MIN_DISTANCE: f32 = 2.5;
+const ORBIT_MAX_DISTANCE: f32 = 30.0;
+
+const TONEMAPPINGS: [(Tonemapping, &str); 8] = [
+    (Tonemapping::None, "None"),
+    (Tonemapping::Reinhard, "Reinhard"),
+    (Tonemapping::ReinhardLuminance, "Reinhard luminance"),
+    (Tonemapping::AcesFitted, "ACES fitted"),
+    (Tonemapping::AgX, "AgX"),
+    (Tonemapping::SomewhatBoringDisplayTransform, "Somewhat boring display transform"),
+    (Tonemapping::TonyMcMapface, "Tony McMapface"),
+    (Tonemapping::BlenderFilmic, "Blender filmic"),
+];
+
+/// Pauses the simulation and lets us orbit around the car to take pictures, toggled with P.
+#[derive(Resource, Default)]
+pub struct PhotoMode {
+    active: bool,
+    yaw: f32,
+    pitch: f32,
+    distance: f32,
+    /// The camera settings to restore when leaving the photo mode.
+    saved_settings: Option<(ColorGrading, Tonemapping, BloomSettings)>,
+    /// The panel is hidden for one frame to not be part of the screenshot.
+    pending_screenshot: bool,
+}
+
+/// A run condition for the systems that must not run while taking pictures.
+pub fn photo_mode_inactive(photo_mode: Res<PhotoMode>) -> bool {
+    !photo_mode.active
+}
+
+/// Every race starts out of the photo mode, the camera it changed went with the last one.
+pub fn reset_photo_mode(
+    mut photo_mode: ResMut<PhotoMode>,
+    mut physics_time: ResMut<Time<Physics>>,
+) {
+    if photo_mode.active {
+        physics_time.unpause();
+    }
+    *photo_mode = PhotoMode::default();
+}
+
+pub fn toggle_photo_mode(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut photo_mode: ResMut<PhotoMode>,
+    mut physics_time: ResMut<Time<Physics>>,
+    mut camera_q: Query<
+        (Entity, &Transform, &mut ColorGrading, &mut Tonemapping, &mut BloomSettings),
+        With<MainCamera>,
+    >,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    if !keys.just_pressed(KeyCode::P) {
+        return;
+    }
+
+    let Ok((camera, camera_transform, mut color_grading, mut tonemapping, mut bloom)) =
+        camera_q.get_single_mut()
+    else {
+        return;
+    };
+
+    if photo_mode.active {
+        if let Some((saved_grading, saved_tonemapping, saved_bloom)) =
+            photo_mode.saved_settings.take()
+        {
+            *color_grading = saved_grading;
+            *tonemapping = saved_tonemapping;
+            *bloom = saved_bloom;
+        }
+        // Get back smoothly to the driving camera
+        commands.entity(camera).insert(CameraTransition::new(*camera_transform));
+        physics_time.unpause();
+        photo_mode.active = false;
+    } else {
+        let Ok(car_transform) = car_q.get_single() else { return };
+
+        // Start orbiting from where the camera currently is
+        let offset = camera_transform.translation - orbit_target(car_transform);
+        let distance = offset.length().clamp(ORBIT_MIN_DISTANCE, ORBIT_MAX_DISTANCE);
+        photo_mode.yaw = offset.x.atan2(offset.z);
+        photo_mode.pitch = (offset.y / offset.length().max(f32::EPSILON)).asin();
+        photo_mode.distance = distance;
+        photo_mode.saved_settings = Some((color_grading.clone(), *tonemapping, bloom.clone()));
+        physics_time.pause();
+        photo_mode.active = true;
+    }
+}
+
+fn orbit_target(car_transform: &Transform) -> Vec3 {
+    car_transform.translation + Vec3::Y * 0.5
+}
+
+pub fn orbit_photo_camera(
+    mut photo_mode: ResMut<PhotoMode>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut contexts: EguiContexts,
+    mut camera_q: Query<&mut Transform, (With<MainCamera>, Without<PlayerCar>)>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    let mouse_delta: Vec2 = mouse_motion.read().map(|motion| motion.delta).sum();
+    let scroll: f32 = mouse_wheel.read().map(|wheel| wheel.y).sum();
+
+    if !photo_mode.active {
+        return;
+    }
+
+    let Ok(mut camera_transform) = camera_q.get_single_mut() else { return };
+    let Ok(car_transform) = car_q.get_single() else { return };
+
+    // Don't move the camera while using the settings panel
+    if !contexts.ctx_mut().wants_pointer_input() {
+        if mouse_buttons.pressed(MouseButton::Left) {
+            photo_mode.yaw -= mouse_delta.x * ORBIT_MOUSE_SENSITIVITY;
+            photo_mode.pitch += mouse_delta.y * ORBIT_MOUSE_SENSITIVITY;
+            photo_mode.pitch = photo_mode.pitch.clamp(-0.2, FRAC_PI_2 - 0.05);
+        }
+        photo_mode.distance = (photo_mode.distance * (1.0 - scroll * 0.1))
+            .clamp(ORBIT_MIN_DISTANCE, ORBIT_MAX_DISTANCE);
+    }
+
+    let rotation = Quat::from_euler(EulerRot::YXZ, photo_mode.yaw, -photo_mode.pitch, 0.0);
+    let target = orbit_target(car_transform);
+    *camera_transform =
+        Transform::from_translation(target + rotation * Vec3::Z * photo_mode.distance)
+            .looking_at(target, Vec3::Y);
+}
+
+pub fn photo_mode_panel(
+    mut photo_mode: ResMut<PhotoMode>,
+    mut contexts: EguiContexts,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    window_q: Query<Entity, With<PrimaryWindow>>,
+    mut camera_q: Query<
+        (&mut ColorGrading, &mut Tonemapping, &mut BloomSettings),
+        With<MainCamera>,
+    >,
+) {
+    if !photo_mode.active {
+        return;
+    }
+
+    if photo_mode.pending_screenshot {
+        photo_mode.pending_screenshot = false;
+        let Ok(window) = window_q.get_single() else { return };
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path = format!("screenshot-{timestamp}.png");
+        match screenshot_manager.save_screenshot_to_disk(window, &path) {
+            Ok(()) => info!("saving a screenshot to {path}"),
+            Err(e) => error!("could not take a screenshot: {e}"),
+        }
+        return;
+    }
+
+    let Ok((mut color_grading, mut tonemapping, mut bloom)) = camera_q.get_single_mut() else {
+        return;
+    };
+
+    egui::Window::new("Photo mode").show(contexts.ctx_mut(), |ui| {
+        ui.add(egui::Slider::new(&mut color_grading.exposure, -4.0..=4.0).text("exposure"));
+        ui.add(egui::Slider::new(&mut color_grading.gamma, 0.5..=2.0).text("gamma"));
+        ui.add(egui::Slider::new(&mut color_grading.post_saturation, 0.0..=2.0).text("saturation"));
+
+        let selected =
+            TONEMAPPINGS.iter().find(|(t, _)| *t == *tonemapping).map_or("Custom", |(_, n)| n);
+        egui::ComboBox::from_label("tonemapping").selected_text(selected).show_ui(ui, |ui| {
+            for (value, name) in TONEMAPPINGS {
+                ui.selectable_value(tonemapping.as_mut(), value, name);
+            }
+        });
+
+        ui.add(egui::Slider::new(&mut bloom.intensity, 0.0..=1.0).text("bloom"));
+
+        ui.separator();
+        ui.label("Drag with the left mouse button to orbit, scroll to zoom.");
+        if ui.button("Save screenshot").clicked() {
+            photo_mode.pending_screenshot = true;
+        }
+    });
+}
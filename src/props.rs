@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::car_collision::{CarCollision, PreviousVelocity};
+use crate::endless::RoadRng;
+use crate::road_geometry::RoadLanes;
+use crate::{PlayerCar, RaceEntity};
+
+/// How far from the player the props never appear, to keep the start clear.
+const PLAYER_CLEARANCE: f32 = 80.0;
+
+/// How high above the road the props are dropped.
+const DROP_HEIGHT: f32 = 0.05;
+
+/// How many pieces a prop breaks into, stacked from its bottom to its top.
+const PIECES: usize = 4;
+
+/// How fast the pieces of a broken prop fly apart, in m/s.
+const PIECES_SPREAD: f32 = 2.0;
+
+/// The things left on the road, knocked over by the cars.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub enum PropKind {
+    Cone,
+    Barrel,
+    Barrier,
+    Crate,
+}
+
+impl PropKind {
+    /// The width, height and length of the prop.
+    pub fn size(self) -> Vec3 {
+        match self {
+            PropKind::Cone => Vec3::new(0.36, 0.7, 0.36),
+            PropKind::Barrel => Vec3::new(0.6, 0.9, 0.6),
+            PropKind::Barrier => Vec3::new(2.0, 0.8, 0.5),
+            PropKind::Crate => Vec3::splat(0.8),
+        }
+    }
+
+    /// The mass in the units of the car, which weighs 30.
+    pub fn mass(self) -> f32 {
+        match self {
+            PropKind::Cone => 0.12,
+            PropKind::Barrel => 0.5,
+            PropKind::Barrier => 2.5,
+            PropKind::Crate => 0.7,
+        }
+    }
+
+    /// The impact speed, in m/s, breaking the prop into pieces, the others only fall over.
+    pub fn break_speed(self) -> Option<f32> {
+        match self {
+            PropKind::Crate => Some(6.0),
+            _ => None,
+        }
+    }
+
+    fn collider(self) -> Collider {
+        let Vec3 { x: width, y: height, z: length } = self.size();
+        match self {
+            PropKind::Cone => Collider::cone(height, width / 2.0),
+            PropKind::Barrel => Collider::cylinder(height, width / 2.0),
+            PropKind::Barrier | PropKind::Crate => Collider::cuboid(width, height, length),
+        }
+    }
+
+    /// The density giving the collider the mass of the prop.
+    fn density(self) -> f32 {
+        let Vec3 { x: width, y: height, z: length } = self.size();
+        let disc = PI * width * width / 4.0;
+        let volume = match self {
+            PropKind::Cone => disc * height / 3.0,
+            PropKind::Barrel => disc * height,
+            PropKind::Barrier | PropKind::Crate => width * height * length,
+        };
+        self.mass() / volume
+    }
+
+    fn mesh(self) -> Mesh {
+        let Vec3 { x: width, y: height, z: length } = self.size();
+        match self {
+            PropKind::Cone => cone_mesh(width / 2.0, height, 16),
+            PropKind::Barrel => {
+                shape::Cylinder { radius: width / 2.0, height, resolution: 16, segments: 1 }.into()
+            }
+            PropKind::Barrier | PropKind::Crate => shape::Box::new(width, height, length).into(),
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            PropKind::Cone => Color::rgb(1.0, 0.35, 0.0),
+            PropKind::Barrel => Color::rgb(0.95, 0.45, 0.1),
+            PropKind::Barrier => Color::rgb(0.85, 0.1, 0.1),
+            PropKind::Crate => Color::rgb(0.55, 0.38, 0.2),
+        }
+    }
+
+    /// Where the props of a group are, from the middle of a lane: to the right, up and
+    /// ahead.
+    fn layout(self, lane_width: f32) -> Vec<Vec3> {
+        match self {
+            // Closing the lane from its left side
+            PropKind::Cone => (0..5)
+                .map(|i| Vec3::new((i as f32 / 4.0 - 0.5) * lane_width, 0.0, 3.0 * i as f32))
+                .collect(),
+            PropKind::Barrel => {
+                (-1..=1).map(|i| Vec3::new(i as f32 * lane_width / 3.0, 0.0, 0.0)).collect()
+            }
+            PropKind::Barrier => vec![Vec3::ZERO],
+            PropKind::Crate => {
+                let height = self.size().y;
+                vec![Vec3::new(-0.45, 0.0, 0.0), Vec3::new(0.45, 0.0, 0.0), Vec3::Y * height]
+            }
+        }
+    }
+}
+
+/// A cone standing on its base, its middle at the origin like the cone collider.
+fn cone_mesh(radius: f32, height: f32, resolution: usize) -> Mesh {
+    let (mut positions, mut normals, mut uvs, mut indices) = (vec![], vec![], vec![], vec![]);
+    let slope = radius / height;
+    for i in 0..resolution {
+        let (a, b) = (i as f32, (i + 1) as f32);
+        let angle = |t: f32| t / resolution as f32 * 2.0 * PI;
+        let rim =
+            |t: f32| Vec3::new(angle(t).cos() * radius, -height / 2.0, angle(t).sin() * radius);
+        let normal = |t: f32| Vec3::new(angle(t).cos(), slope, angle(t).sin()).normalize();
+        let first = positions.len() as u32;
+
+        // The side, with the normal of its middle at the apex
+        positions.extend([rim(a), Vec3::Y * height / 2.0, rim(b)].map(Vec3::to_array));
+        normals.extend([normal(a), normal((a + b) / 2.0), normal(b)].map(Vec3::to_array));
+        // The base
+        positions.extend([rim(a), rim(b), Vec3::NEG_Y * height / 2.0].map(Vec3::to_array));
+        normals.extend([[0.0, -1.0, 0.0]; 3]);
+
+        uvs.extend([[a / resolution as f32, 1.0], [0.5, 0.0], [b / resolution as f32, 1.0]]);
+        uvs.extend([[0.0, 0.0]; 3]);
+        indices.extend(first..first + 6);
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
+/// The props left along the roads of a map.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PropsDefinition {
+    /// The average distance, in meters, between two groups of props.
+    pub spacing: f32,
+    /// Seeds the kinds, lanes and spacing of the props.
+    #[serde(default)]
+    pub seed: u64,
+    #[serde(default = "all_kinds")]
+    pub kinds: Vec<PropKind>,
+}
+
+fn all_kinds() -> Vec<PropKind> {
+    vec![PropKind::Cone, PropKind::Barrel, PropKind::Barrier, PropKind::Crate]
+}
+
+/// Places the props on the roads of the map as they are built.
+#[derive(Resource)]
+pub struct Props {
+    definition: PropsDefinition,
+    rng: RoadRng,
+    assets: Option<PropAssets>,
+}
+
+impl Props {
+    pub fn new(definition: &PropsDefinition) -> Props {
+        Props { definition: definition.clone(), rng: RoadRng::new(definition.seed), assets: None }
+    }
+}
+
+struct PropAssets {
+    kinds: HashMap<PropKind, (Handle<Mesh>, Handle<StandardMaterial>)>,
+}
+
+impl PropAssets {
+    fn new(meshes: &mut Assets<Mesh>, materials: &mut Assets<StandardMaterial>) -> PropAssets {
+        let kinds = all_kinds()
+            .into_iter()
+            .map(|kind| {
+                let material = StandardMaterial {
+                    base_color: kind.color(),
+                    perceptual_roughness: 0.7,
+                    ..default()
+                };
+                (kind, (meshes.add(kind.mesh()), materials.add(material)))
+            })
+            .collect();
+        PropAssets { kinds }
+    }
+}
+
+/// A prop knocked around by the cars, despawned with the road it was left on.
+#[derive(Component, Debug)]
+pub struct Prop {
+    pub kind: PropKind,
+    road: Entity,
+}
+
+/// A piece of a broken prop.
+#[derive(Component, Debug)]
+pub struct Debris;
+
+fn prop_bundle(
+    kind: PropKind,
+    collider: Collider,
+    density: f32,
+    transform: Transform,
+    (mesh, material): (Handle<Mesh>, Handle<StandardMaterial>),
+) -> impl Bundle {
+    (
+        RaceEntity,
+        RigidBody::Dynamic,
+        collider,
+        ColliderDensity(density),
+        PreviousVelocity::default(),
+        PbrBundle { mesh, material, transform, ..default() },
+        Name::new(format!("{kind:?}")),
+    )
+}
+
+/// Leaves groups of props in the lanes of every new road.
+pub fn place_props(
+    mut commands: Commands,
+    mut props: ResMut<Props>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    player_q: Query<&Transform, With<PlayerCar>>,
+    roads_q: Query<(Entity, &RoadLanes, &Transform), Added<RoadLanes>>,
+) {
+    if props.definition.kinds.is_empty() || props.definition.spacing <= 0.0 {
+        return;
+    }
+    let props = &mut *props;
+    let assets = props.assets.get_or_insert_with(|| PropAssets::new(&mut meshes, &mut materials));
+    let player = player_q.get_single().ok().map(|transform| transform.translation);
+
+    for (road, lanes, road_transform) in &roads_q {
+        let Some(first) = lanes.samples.first() else { continue };
+        let spacing = props.definition.spacing;
+        let mut next = first.distance + spacing * (0.5 + props.rng.unit());
+
+        for sample in &lanes.samples {
+            if sample.distance < next {
+                continue;
+            }
+            next += spacing * (0.5 + props.rng.unit());
+
+            let kinds = &props.definition.kinds;
+            let kind = kinds[(props.rng.next() % kinds.len() as u64) as usize];
+            let lane = (props.rng.next() % lanes.lanes.max(1) as u64) as u32;
+            let lane_width = sample.width / lanes.lanes.max(1) as f32;
+            let (right, up) = (sample.right(), sample.up());
+            let middle = sample.position + right * sample.lane_offset(lanes.lanes, lane);
+            let rotation = road_transform.rotation
+                * Transform::IDENTITY.looking_to(sample.forward, up).rotation;
+
+            for offset in kind.layout(lane_width) {
+                let local = middle
+                    + right * offset.x
+                    + up * (offset.y + kind.size().y / 2.0 + DROP_HEIGHT)
+                    + sample.forward * offset.z;
+                let translation = road_transform.transform_point(local);
+                if player.map_or(false, |player| player.distance(translation) < PLAYER_CLEARANCE) {
+                    continue;
+                }
+                let transform = Transform { translation, rotation, ..default() };
+                commands.spawn((
+                    Prop { kind, road },
+                    prop_bundle(
+                        kind,
+                        kind.collider(),
+                        kind.density(),
+                        transform,
+                        assets.kinds[&kind].clone(),
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+/// Breaks the props hit hard enough into pieces flying apart.
+pub fn break_props(
+    mut commands: Commands,
+    mut collisions: EventReader<CarCollision>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    props_q: Query<
+        (&Prop, &Transform, &LinearVelocity, &Handle<StandardMaterial>),
+        Without<Debris>,
+    >,
+) {
+    let mut broken = Vec::new();
+    for collision in collisions.read() {
+        let Ok((prop, transform, velocity, material)) = props_q.get(collision.other) else {
+            continue;
+        };
+        let Some(break_speed) = prop.kind.break_speed() else { continue };
+        if collision.impact_speed < break_speed || broken.contains(&collision.other) {
+            continue;
+        }
+        broken.push(collision.other);
+        commands.entity(collision.other).despawn_recursive();
+
+        // Slices of the prop, stacked and weighing as much as it
+        let size = prop.kind.size();
+        let piece_size = Vec3::new(size.x, size.y / PIECES as f32, size.z);
+        let mesh = meshes.add(shape::Box::new(piece_size.x, piece_size.y, piece_size.z).into());
+        for piece in 0..PIECES {
+            let height = (piece as f32 + 0.5) * piece_size.y - size.y / 2.0;
+            // Every slice flies up and to its own side
+            let side = Quat::from_axis_angle(transform.up(), piece as f32 * PI / 2.0);
+            let spread = (side * transform.right() + transform.up()) * PIECES_SPREAD;
+            commands.spawn((
+                Debris,
+                Prop { kind: prop.kind, road: prop.road },
+                prop_bundle(
+                    prop.kind,
+                    Collider::cuboid(piece_size.x, piece_size.y, piece_size.z),
+                    prop.kind.density(),
+                    Transform {
+                        translation: transform.transform_point(Vec3::Y * height),
+                        rotation: transform.rotation,
+                        ..default()
+                    },
+                    (mesh.clone(), material.clone()),
+                ),
+                LinearVelocity(velocity.0 + spread),
+            ));
+        }
+    }
+}
+
+/// Removes the props with the road they were left on, behind the endless road.
+pub fn despawn_road_props(
+    mut commands: Commands,
+    props_q: Query<(Entity, &Prop)>,
+    roads_q: Query<(), With<RoadLanes>>,
+) {
+    for (entity, prop) in &props_q {
+        if !roads_q.contains(prop.road) {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
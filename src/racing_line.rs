@@ -0,0 +1,168 @@
+use bevy::prelude::*;
+
+use crate::road_geometry::{RoadLanes, RoadSample};
+
+/// How many times every point of the line is pulled between its neighbors.
+const RELAXATION_ITERATIONS: usize = 300;
+
+/// How far the line keeps from the edges of the lanes.
+const EDGE_MARGIN: f32 = 1.5;
+
+/// How many points apart the curvature is measured, to smooth it.
+const CURVATURE_SPAN: usize = 3;
+
+/// A point of the racing line, relative to the road entity.
+#[derive(Debug, Clone, Copy)]
+pub struct RacingPoint {
+    pub position: Vec3,
+    pub forward: Vec3,
+    /// The right side of the road.
+    pub right: Vec3,
+    /// The distance along the road, the line has a point for every sample of the road.
+    pub distance: f32,
+    /// The inverse of the radius of the turn, seen from above.
+    pub curvature: f32,
+    /// How far the line is from the middle of the road, positive to the right.
+    pub offset: f32,
+    /// How far from the middle of the road a car can drive.
+    pub half_width: f32,
+}
+
+/// The line cutting the corners of a road, found by pulling it as straight as the lanes allow.
+#[derive(Component, Debug, Clone)]
+pub struct RacingLine {
+    pub points: Vec<RacingPoint>,
+    /// The last point is back on the first one.
+    pub closed: bool,
+}
+
+impl RacingLine {
+    pub fn new(lanes: &RoadLanes) -> RacingLine {
+        let samples = &lanes.samples;
+        // The last sample of a closed road is the first one
+        let count = if lanes.closed { samples.len().saturating_sub(1) } else { samples.len() };
+        let neighbor = |index: usize, step: isize| -> Option<usize> {
+            let neighbor = index as isize + step;
+            match lanes.closed {
+                true if count > 0 => Some(neighbor.rem_euclid(count as isize) as usize),
+                _ => (0..count as isize).contains(&neighbor).then_some(neighbor as usize),
+            }
+        };
+
+        let rights: Vec<_> = samples.iter().map(RoadSample::right).collect();
+        let half_widths: Vec<_> =
+            samples.iter().map(|sample| (sample.width / 2.0 - EDGE_MARGIN).max(0.0)).collect();
+        let mut offsets = vec![0.0; samples.len()];
+
+        for _ in 0..RELAXATION_ITERATIONS {
+            for index in 0..count {
+                // The ends of an open road stay in the middle
+                let (Some(previous), Some(next)) = (neighbor(index, -1), neighbor(index, 1)) else {
+                    continue;
+                };
+                let point = |i: usize| samples[i].position + rights[i] * offsets[i];
+                let middle = (point(previous) + point(next)) / 2.0;
+                let offset = (middle - samples[index].position).dot(rights[index]);
+                offsets[index] = offset.clamp(-half_widths[index], half_widths[index]);
+            }
+        }
+        if lanes.closed && count > 0 {
+            offsets[count] = offsets[0];
+        }
+
+        let positions: Vec<_> =
+            (0..samples.len()).map(|i| samples[i].position + rights[i] * offsets[i]).collect();
+        let points = (0..samples.len())
+            .map(|index| {
+                let around = |step: isize| neighbor(index % count.max(1), step).unwrap_or(index);
+                let forward = (positions[around(1)] - positions[around(-1)]).normalize_or_zero();
+                let span = CURVATURE_SPAN as isize;
+                let curvature =
+                    curvature(positions[around(-span)], positions[index], positions[around(span)]);
+                RacingPoint {
+                    position: positions[index],
+                    forward: if forward == Vec3::ZERO { samples[index].forward } else { forward },
+                    right: rights[index],
+                    distance: samples[index].distance,
+                    curvature,
+                    offset: offsets[index],
+                    half_width: half_widths[index],
+                }
+            })
+            .collect();
+
+        RacingLine { points, closed: lanes.closed }
+    }
+
+    /// The index of the point nearest to a position relative to the road.
+    pub fn nearest(&self, position: Vec3) -> Option<usize> {
+        (0..self.points.len()).min_by(|&a, &b| {
+            let distance = |index: usize| self.points[index].position.distance_squared(position);
+            distance(a).total_cmp(&distance(b))
+        })
+    }
+
+    /// The point after this one, going around a closed line.
+    pub fn next(&self, index: usize) -> Option<usize> {
+        match index + 1 {
+            next if next < self.points.len() => Some(next),
+            // The first point is where the last one is
+            _ if self.closed && self.points.len() > 1 => Some(1),
+            _ => None,
+        }
+    }
+
+    pub fn previous(&self, index: usize) -> Option<usize> {
+        match index {
+            0 if self.closed && self.points.len() > 1 => Some(self.points.len() - 2),
+            0 => None,
+            _ => Some(index - 1),
+        }
+    }
+
+    /// The index of the point about `ahead` meters after this one.
+    pub fn index_ahead(&self, from: usize, ahead: f32) -> usize {
+        self.walk(from, ahead, RacingLine::next).0
+    }
+
+    /// The index of the point about `behind` meters before this one and how far it is,
+    /// stopping at the start of an open line.
+    pub fn index_behind(&self, from: usize, behind: f32) -> (usize, f32) {
+        self.walk(from, behind, RacingLine::previous)
+    }
+
+    fn walk(
+        &self,
+        from: usize,
+        distance: f32,
+        step: fn(&Self, usize) -> Option<usize>,
+    ) -> (usize, f32) {
+        let (mut index, mut travelled) = (from, 0.0);
+        while travelled < distance {
+            let Some(next) = step(self, index) else { break };
+            travelled += self.points[index].position.distance(self.points[next].position);
+            index = next;
+        }
+        (index, travelled)
+    }
+}
+
+/// The curvature of the circle going through three points, seen from above.
+fn curvature(a: Vec3, b: Vec3, c: Vec3) -> f32 {
+    let (a, b, c) = (a.xz(), b.xz(), c.xz());
+    let lengths = a.distance(b) * b.distance(c) * c.distance(a);
+    if lengths <= f32::EPSILON {
+        return 0.0;
+    }
+    2.0 * (b - a).perp_dot(c - a).abs() / lengths
+}
+
+/// Finds the racing line of every new road.
+pub fn build_racing_lines(
+    mut commands: Commands,
+    roads_q: Query<(Entity, &RoadLanes), Added<RoadLanes>>,
+) {
+    for (road, lanes) in &roads_q {
+        commands.entity(road).insert(RacingLine::new(lanes));
+    }
+}
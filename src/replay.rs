@@ -0,0 +1,178 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy::utils::Duration;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::car_input::CarInput;
+use crate::floating_origin::FloatingOrigin;
+use crate::PlayerCar;
+
+/// The default time step used when a replay doesn't tell us which one to use.
+const DEFAULT_FRAME_DELTA: Duration = Duration::from_nanos(1_000_000_000 / 144);
+
+/// A recorded driving session: the inputs of every frame and where the car ended up.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct Replay {
+    /// The map the session was driven on.
+    pub map: String,
+    /// The tuning preset of the car, the default car when there is none.
+    pub car: Option<String>,
+    pub frames: Vec<ReplayFrame>,
+    pub final_translation: [f32; 3],
+    pub final_rotation: [f32; 4],
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct ReplayFrame {
+    /// The duration of the frame, replayed as-is to get the same integration steps.
+    pub delta: Duration,
+    pub input: CarInput,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> bincode::Result<Replay> {
+        let file = File::open(path)?;
+        bincode::deserialize_from(BufReader::new(file))
+    }
+
+    pub fn save(&self, path: &Path) -> bincode::Result<()> {
+        let file = File::create(path)?;
+        bincode::serialize_into(BufWriter::new(file), self)
+    }
+
+    pub fn final_transform(&self) -> Transform {
+        Transform::from_translation(Vec3::from_array(self.final_translation))
+            .with_rotation(Quat::from_array(self.final_rotation))
+    }
+}
+
+/// Records the inputs of the player car and writes them to disk when the app exits.
+#[derive(Resource)]
+pub struct ReplayRecorder {
+    path: PathBuf,
+    replay: Replay,
+}
+
+impl ReplayRecorder {
+    pub fn new(path: PathBuf, map: String) -> ReplayRecorder {
+        ReplayRecorder { path, replay: Replay { map, ..default() } }
+    }
+}
+
+/// Starts the physics steps of every race in phase, the time left over from the loading
+/// frames differs between recording and replaying.
+///
+/// The replays are recorded from the first frame of the race, replaying them from there
+/// then steps the physics with the same inputs at the same times.
+pub fn reset_physics_overstep(mut physics_time: ResMut<Time<Physics>>) {
+    if let TimestepMode::Fixed { overstep, .. } = physics_time.timestep_mode_mut() {
+        *overstep = Duration::ZERO;
+    }
+}
+
+pub fn record_replay_frame(
+    time: Res<Time>,
+    mut recorder: ResMut<ReplayRecorder>,
+    car_query: Query<&CarInput, With<PlayerCar>>,
+) {
+    let Ok(&input) = car_query.get_single() else { return };
+    recorder.replay.frames.push(ReplayFrame { delta: time.delta(), input });
+}
+
+pub fn save_replay_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut recorder: ResMut<ReplayRecorder>,
+    origin: Res<FloatingOrigin>,
+    car_query: Query<&Transform, With<PlayerCar>>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+
+    if let Ok(car_transform) = car_query.get_single() {
+        let translation = origin.absolute(car_transform.translation).as_vec3();
+        recorder.replay.final_translation = translation.to_array();
+        recorder.replay.final_rotation = car_transform.rotation.to_array();
+    }
+
+    let ReplayRecorder { path, replay } = &*recorder;
+    match replay.save(path) {
+        Ok(()) => info!("saved {} replay frames to {}", replay.frames.len(), path.display()),
+        Err(e) => error!("could not save the replay to {}: {e}", path.display()),
+    }
+}
+
+/// Drives the car from a recorded replay and compares the final transform with the recorded one.
+#[derive(Resource)]
+pub struct ReplayPlayback {
+    replay: Replay,
+    cursor: usize,
+    /// Maximum distance, in meters, the car can be away from the recorded final position.
+    tolerance: f32,
+    /// Set when the replay ended too far from the recorded final transform.
+    diverged: Arc<AtomicBool>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay, tolerance: f32, diverged: Arc<AtomicBool>) -> ReplayPlayback {
+        ReplayPlayback { replay, cursor: 0, tolerance, diverged }
+    }
+
+    /// The time update strategy to use until the car is spawned, the loading frames don't
+    /// count as the physics overstep is reset when the race starts.
+    pub fn initial_time_strategy(&self) -> TimeUpdateStrategy {
+        let delta = self.replay.frames.first().map_or(DEFAULT_FRAME_DELTA, |f| f.delta);
+        TimeUpdateStrategy::ManualDuration(delta)
+    }
+}
+
+pub fn apply_replay_input(
+    playback: Res<ReplayPlayback>,
+    mut car_query: Query<&mut CarInput, With<PlayerCar>>,
+) {
+    let Ok(mut car_input) = car_query.get_single_mut() else { return };
+    if let Some(frame) = playback.replay.frames.get(playback.cursor) {
+        car_input.set_if_neq(frame.input);
+    }
+}
+
+pub fn advance_replay_playback(
+    mut playback: ResMut<ReplayPlayback>,
+    mut time_strategy: ResMut<TimeUpdateStrategy>,
+    mut app_exit: EventWriter<AppExit>,
+    origin: Res<FloatingOrigin>,
+    car_query: Query<&Transform, With<PlayerCar>>,
+) {
+    let Ok(car_transform) = car_query.get_single() else { return };
+
+    playback.cursor += 1;
+    if let Some(frame) = playback.replay.frames.get(playback.cursor) {
+        // The next frame must last exactly as long as the recorded one
+        *time_strategy = TimeUpdateStrategy::ManualDuration(frame.delta);
+        return;
+    }
+
+    let expected = playback.replay.final_transform();
+    let translation = origin.absolute(car_transform.translation).as_vec3();
+    let distance = expected.translation.distance(translation);
+    let angle = expected.rotation.angle_between(car_transform.rotation).to_degrees();
+    let diverged = distance > playback.tolerance;
+
+    println!("replayed {} frames", playback.replay.frames.len());
+    println!("expected translation: {}", expected.translation);
+    println!("actual translation:   {translation}");
+    println!("translation divergence: {distance:.6} m (tolerance {} m)", playback.tolerance);
+    println!("rotation divergence:    {angle:.6}°");
+    println!("{}", if diverged { "FAILED: the handling changed" } else { "OK" });
+
+    playback.diverged.store(diverged, Ordering::SeqCst);
+    app_exit.send(AppExit);
+}
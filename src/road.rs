@@ -0,0 +1,111 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::asset::io::file::FileAssetReader;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::map::Surface;
+use crate::road_geometry::{
+    build_road_geometry, sample_spline, RoadGeometry, RoadPoint, RoadProfile, RoadSample,
+};
+
+/// The directory, relative to the assets, where the road splines are.
+pub const ROADS_DIRECTORY: &str = "roads";
+
+/// A road going through its control points, saved in the roads directory.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RoadSpline {
+    pub points: Vec<RoadPoint>,
+    /// The last point is joined back to the first one.
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub profile: RoadProfile,
+}
+
+impl RoadSpline {
+    pub fn load(path: &Path) -> Result<RoadSpline, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        Ok(ron::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    pub fn sample(&self) -> Vec<RoadSample> {
+        sample_spline(&self.points, self.closed)
+    }
+}
+
+/// The path of a road file from its name.
+pub fn road_path(name: &str) -> PathBuf {
+    FileAssetReader::get_base_path()
+        .join("assets")
+        .join(ROADS_DIRECTORY)
+        .join(format!("{name}.ron"))
+}
+
+pub fn list_roads() -> Vec<String> {
+    let directory = FileAssetReader::get_base_path().join("assets").join(ROADS_DIRECTORY);
+    let mut roads: Vec<_> = fs::read_dir(directory)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    roads.sort_unstable();
+    roads
+}
+
+/// A road built from a spline.
+#[derive(Component)]
+pub struct Road;
+
+/// The material of every road, colored by the strips of its mesh.
+pub fn road_material(materials: &mut Assets<StandardMaterial>) -> Handle<StandardMaterial> {
+    materials.add(StandardMaterial {
+        base_color: Color::WHITE,
+        perceptual_roughness: 0.9,
+        ..default()
+    })
+}
+
+/// Spawns the road mesh of the spline with a matching static collider.
+pub fn spawn_road(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<StandardMaterial>,
+    spline: &RoadSpline,
+) -> Result<Entity, Box<dyn Error>> {
+    let geometry = build_road_geometry(&spline.sample(), &spline.profile)?;
+    Ok(spawn_road_geometry(commands, meshes, road_material(materials), geometry))
+}
+
+pub fn spawn_road_geometry(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<StandardMaterial>,
+    geometry: RoadGeometry,
+) -> Entity {
+    let RoadGeometry { mesh, collider, lanes } = geometry;
+    commands
+        .spawn((
+            Road,
+            lanes,
+            RigidBody::Static,
+            collider,
+            Surface::Asphalt,
+            PbrBundle { mesh: meshes.add(mesh), material, ..default() },
+        ))
+        .id()
+}
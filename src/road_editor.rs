@@ -0,0 +1,347 @@
+use std::error::Error;
+
+use bevy::prelude::*;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContexts;
+use bevy_inspector_egui::egui;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::camera::{CameraMode, CameraTransition};
+use crate::floating_origin::FloatingOrigin;
+use crate::road::{list_roads, road_path, spawn_road, RoadSpline};
+use crate::road_geometry::RoadPoint;
+use crate::{MainCamera, PlayerCar, RaceEntity};
+
+/// How close to a control point, in pixels, a click selects it.
+const PICK_RADIUS: f32 = 12.0;
+
+const DEFAULT_ROAD_WIDTH: f32 = 8.0;
+
+/// Authors road splines in game, toggled with F6.
+#[derive(Resource, Default)]
+pub struct RoadEditor {
+    active: bool,
+    spline: RoadSpline,
+    name: String,
+    roads: Vec<String>,
+    selected: Option<usize>,
+    dragging: bool,
+    /// The spline changed since its preview was built.
+    changed: bool,
+    message: Option<String>,
+    /// The camera mode to restore when leaving the editor.
+    saved_camera_mode: Option<CameraMode>,
+}
+
+impl RoadEditor {
+    /// Moves the road being edited with the floating origin.
+    pub fn shift(&mut self, offset: Vec3) {
+        move_points(&mut self.spline, |position| position + offset);
+    }
+}
+
+fn move_points(spline: &mut RoadSpline, move_point: impl Fn(Vec3) -> Vec3) {
+    for point in &mut spline.points {
+        point.position = move_point(point.position()).to_array();
+    }
+}
+
+/// The road being edited, rebuilt whenever its spline changes.
+#[derive(Component)]
+pub struct RoadPreview;
+
+/// A run condition for the systems that must not run while editing a road.
+pub fn road_editor_inactive(editor: Res<RoadEditor>) -> bool {
+    !editor.active
+}
+
+/// Every race starts out of the editor, the road being edited went with the last one.
+pub fn reset_road_editor(
+    mut editor: ResMut<RoadEditor>,
+    mut mode: ResMut<CameraMode>,
+    mut physics_time: ResMut<Time<Physics>>,
+) {
+    if editor.active {
+        *mode = editor.saved_camera_mode.take().unwrap_or_default();
+        physics_time.unpause();
+    }
+    *editor = RoadEditor::default();
+}
+
+/// Pauses the simulation and flies the camera freely while editing.
+pub fn toggle_road_editor(
+    mut commands: Commands,
+    keys: Res<Input<KeyCode>>,
+    mut editor: ResMut<RoadEditor>,
+    mut mode: ResMut<CameraMode>,
+    mut physics_time: ResMut<Time<Physics>>,
+    camera_q: Query<(Entity, &Transform), With<MainCamera>>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    if !keys.just_pressed(KeyCode::F6) {
+        return;
+    }
+
+    let Ok((camera, &camera_transform)) = camera_q.get_single() else { return };
+    let Ok(car_transform) = car_q.get_single() else { return };
+
+    if editor.active {
+        *mode = editor.saved_camera_mode.take().unwrap_or_default();
+        physics_time.unpause();
+        editor.active = false;
+        editor.dragging = false;
+    } else {
+        editor.saved_camera_mode = Some(*mode);
+        *mode = CameraMode::FreeFly;
+        editor.roads = list_roads();
+        physics_time.pause();
+        editor.active = true;
+    }
+
+    commands.entity(camera).insert((
+        mode.rig(car_transform, &camera_transform),
+        CameraTransition::new(camera_transform),
+    ));
+}
+
+/// Selects and drags the control points with the left mouse button, Ctrl+click adds a
+/// point after the selected one and Delete removes it.
+pub fn edit_road_points(
+    mut editor: ResMut<RoadEditor>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    keys: Res<Input<KeyCode>>,
+    mut contexts: EguiContexts,
+    window_q: Query<&Window, With<PrimaryWindow>>,
+    camera_q: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+) {
+    if !editor.active {
+        return;
+    }
+
+    if mouse_buttons.just_released(MouseButton::Left) {
+        editor.dragging = false;
+    }
+
+    if keys.just_pressed(KeyCode::Delete) {
+        if let Some(selected) = editor.selected.take() {
+            editor.spline.points.remove(selected);
+            editor.changed = true;
+        }
+    }
+
+    let ctx = contexts.ctx_mut();
+    if !editor.dragging && (ctx.wants_pointer_input() || ctx.is_pointer_over_area()) {
+        return;
+    }
+
+    let Ok(window) = window_q.get_single() else { return };
+    let Some(cursor) = window.cursor_position() else { return };
+    let Ok((camera, camera_transform)) = camera_q.get_single() else { return };
+    let Some(ray) = camera.viewport_to_world(camera_transform, cursor) else { return };
+
+    // The points move on the horizontal plane at their own height
+    let on_plane = |height: f32| {
+        ray.intersect_plane(Vec3::Y * height, Vec3::Y).map(|distance| ray.get_point(distance))
+    };
+
+    if editor.dragging {
+        let Some(selected) = editor.selected else { return };
+        let point = editor.spline.points[selected];
+        if let Some(position) = on_plane(point.position[1]) {
+            if position != point.position() {
+                editor.spline.points[selected].position = position.to_array();
+                editor.changed = true;
+            }
+        }
+        return;
+    }
+
+    if !mouse_buttons.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let picked = editor.spline.points.iter().position(|point| {
+        camera
+            .world_to_viewport(camera_transform, point.position())
+            .map_or(false, |screen| screen.distance(cursor) < PICK_RADIUS)
+    });
+
+    if let Some(picked) = picked {
+        editor.selected = Some(picked);
+        editor.dragging = true;
+    } else if keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]) {
+        let template = editor.selected.or(editor.spline.points.len().checked_sub(1));
+        let template = template.map(|index| editor.spline.points[index]);
+        let height = template.map_or(0.0, |point| point.position[1]);
+        let Some(position) = on_plane(height) else { return };
+
+        let point = RoadPoint::new(
+            position,
+            template.map_or(DEFAULT_ROAD_WIDTH, |point| point.width),
+            template.map_or(0.0, |point| point.banking),
+        );
+        let index = editor.selected.map_or(editor.spline.points.len(), |selected| selected + 1);
+        editor.spline.points.insert(index, point);
+        editor.selected = Some(index);
+        editor.changed = true;
+    } else {
+        editor.selected = None;
+    }
+}
+
+enum RoadEditorAction {
+    Save(String),
+    Load(String),
+    Clear,
+}
+
+fn run_action(
+    editor: &mut RoadEditor,
+    origin: &FloatingOrigin,
+    action: RoadEditorAction,
+) -> Result<String, Box<dyn Error>> {
+    // The roads are saved where they were before the world was moved
+    match action {
+        RoadEditorAction::Save(name) => {
+            let path = road_path(&name);
+            let mut spline = editor.spline.clone();
+            move_points(&mut spline, |position| origin.absolute(position).as_vec3());
+            spline.save(&path)?;
+            editor.roads = list_roads();
+            Ok(format!("saved {}", path.display()))
+        }
+        RoadEditorAction::Load(name) => {
+            let path = road_path(&name);
+            editor.spline = RoadSpline::load(&path)?;
+            move_points(&mut editor.spline, |position| origin.relative(position.as_dvec3()));
+            editor.name = name;
+            editor.selected = None;
+            editor.changed = true;
+            Ok(format!("loaded {}", path.display()))
+        }
+        RoadEditorAction::Clear => {
+            editor.spline = RoadSpline::default();
+            editor.selected = None;
+            editor.changed = true;
+            Ok("cleared the road".to_string())
+        }
+    }
+}
+
+pub fn road_editor_panel(
+    mut editor: ResMut<RoadEditor>,
+    origin: Res<FloatingOrigin>,
+    mut contexts: EguiContexts,
+) {
+    if !editor.active {
+        return;
+    }
+
+    let mut action = None;
+    let before = editor.spline.clone();
+
+    egui::Window::new("Road editor").show(contexts.ctx_mut(), |ui| {
+        let RoadEditor { spline, selected, name, roads, message, .. } = &mut *editor;
+
+        ui.label(format!("{} control points", spline.points.len()));
+        ui.checkbox(&mut spline.closed, "closed loop");
+        let profile = &mut spline.profile;
+        ui.add(egui::Slider::new(&mut profile.lanes, 1..=6).text("lanes"));
+        ui.add(egui::Slider::new(&mut profile.shoulder_width, 0.0..=4.0).text("shoulders"));
+        ui.add(egui::Slider::new(&mut profile.curb_width, 0.0..=2.0).text("curbs"));
+        ui.add(egui::Slider::new(&mut profile.curb_height, 0.0..=0.3).text("curb height"));
+
+        if let Some(index) = *selected {
+            ui.separator();
+            ui.label(format!("point {index}"));
+            let point = &mut spline.points[index];
+            ui.add(egui::Slider::new(&mut point.width, 3.0..=40.0).text("lanes width"));
+            ui.add(egui::Slider::new(&mut point.banking, -30.0..=30.0).text("banking"));
+            ui.add(egui::DragValue::new(&mut point.position[1]).speed(0.1).prefix("elevation "));
+            if ui.button("Remove point").clicked() {
+                spline.points.remove(index);
+                *selected = None;
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(name);
+            let valid_name = !name.trim().is_empty() && !name.contains(['/', '\\']);
+            if ui.add_enabled(valid_name, egui::Button::new("Save")).clicked() {
+                action = Some(RoadEditorAction::Save(name.trim().to_string()));
+            }
+            if ui.button("Clear").clicked() {
+                action = Some(RoadEditorAction::Clear);
+            }
+        });
+
+        egui::Grid::new("roads").striped(true).show(ui, |ui| {
+            for road in roads.iter() {
+                ui.label(road);
+                if ui.button("Load").clicked() {
+                    action = Some(RoadEditorAction::Load(road.clone()));
+                }
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+        ui.label("Ctrl+click to add a point, drag to move it, Delete to remove it.");
+        ui.label("Fly with the right mouse button and WASD, QE.");
+        if let Some(message) = message {
+            ui.label(message);
+        }
+    });
+
+    if editor.spline != before {
+        editor.changed = true;
+    }
+
+    if let Some(action) = action {
+        editor.message = Some(match run_action(&mut editor, &origin, action) {
+            Ok(message) => message,
+            Err(e) => format!("error: {e}"),
+        });
+    }
+}
+
+/// Rebuilds the road mesh and collider whenever the spline changes.
+pub fn preview_road(
+    mut commands: Commands,
+    mut editor: ResMut<RoadEditor>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    preview_q: Query<Entity, With<RoadPreview>>,
+) {
+    if !editor.changed {
+        return;
+    }
+    editor.changed = false;
+
+    for preview in &preview_q {
+        commands.entity(preview).despawn_recursive();
+    }
+
+    if editor.spline.points.len() < 2 {
+        return;
+    }
+    match spawn_road(&mut commands, &mut meshes, &mut materials, &editor.spline) {
+        Ok(road) => {
+            commands.entity(road).insert((RaceEntity, RoadPreview));
+        }
+        Err(e) => editor.message = Some(format!("error: {e}")),
+    }
+}
+
+pub fn draw_road_editor(editor: Res<RoadEditor>, mut gizmos: Gizmos) {
+    if !editor.active {
+        return;
+    }
+
+    gizmos.linestrip(editor.spline.sample().iter().map(|sample| sample.position), Color::YELLOW);
+    for (index, point) in editor.spline.points.iter().enumerate() {
+        let color = if editor.selected == Some(index) { Color::ORANGE_RED } else { Color::WHITE };
+        gizmos.sphere(point.position(), Quat::IDENTITY, 0.6, color);
+    }
+}
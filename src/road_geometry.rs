@@ -0,0 +1,366 @@
+use std::error::Error;
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::render::mesh::Indices;
+use bevy::render::render_resource::PrimitiveTopology;
+use bevy_xpbd_3d::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The distance between two cross-sections of the road mesh.
+const SAMPLE_SPACING: f32 = 2.0;
+
+/// The length of road covered by the texture once.
+const UV_LENGTH: f32 = 10.0;
+
+const LANE_COLOR: Color = Color::rgb(0.2, 0.2, 0.22);
+const SHOULDER_COLOR: Color = Color::rgb(0.35, 0.33, 0.3);
+const CURB_COLOR: Color = Color::rgb(0.8, 0.1, 0.1);
+
+/// A control point of a road spline.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RoadPoint {
+    /// The elevation of the road is the height of its control points.
+    pub position: [f32; 3],
+    /// The width of the lanes, without the shoulders and curbs.
+    pub width: f32,
+    /// The roll of the road in degrees, positive to the right.
+    #[serde(default)]
+    pub banking: f32,
+}
+
+impl RoadPoint {
+    pub fn new(position: Vec3, width: f32, banking: f32) -> RoadPoint {
+        RoadPoint { position: position.to_array(), width, banking }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from(self.position)
+    }
+}
+
+/// A cross-section of the road along its spline.
+#[derive(Debug, Clone, Copy)]
+pub struct RoadSample {
+    pub position: Vec3,
+    pub forward: Vec3,
+    pub width: f32,
+    /// The banking in radians.
+    pub banking: f32,
+    /// The distance from the start of the road.
+    pub distance: f32,
+}
+
+impl RoadSample {
+    /// The right side of the road, tilted by the banking.
+    pub fn right(&self) -> Vec3 {
+        let flat_right = self.forward.cross(Vec3::Y).normalize_or_zero();
+        Quat::from_axis_angle(self.forward, self.banking) * flat_right
+    }
+
+    pub fn up(&self) -> Vec3 {
+        self.right().cross(self.forward).normalize_or_zero()
+    }
+
+    /// The offset of the middle of a lane from the middle of the road, the lanes being
+    /// counted from the left.
+    pub fn lane_offset(&self, lanes: u32, lane: u32) -> f32 {
+        let lanes = lanes.max(1);
+        (lane.min(lanes - 1) as f32 + 0.5) * self.width / lanes as f32 - self.width / 2.0
+    }
+}
+
+/// The cross-section swept along the spline: the lanes, then a shoulder and a curb
+/// on each side.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RoadProfile {
+    pub lanes: u32,
+    pub shoulder_width: f32,
+    pub curb_width: f32,
+    /// The height of the outer edge of the curbs, their inner edge is level with the road.
+    pub curb_height: f32,
+}
+
+impl Default for RoadProfile {
+    fn default() -> RoadProfile {
+        RoadProfile { lanes: 2, shoulder_width: 1.0, curb_width: 0.8, curb_height: 0.1 }
+    }
+}
+
+/// A flat part of the cross-section, from its left to its right edge as `(offset, height)`.
+struct ProfileStrip {
+    left: (f32, f32),
+    right: (f32, f32),
+    color: Color,
+}
+
+impl RoadProfile {
+    /// Every lane is its own strip so that the texture repeats once per lane.
+    fn strips(&self, lanes_width: f32) -> Vec<ProfileStrip> {
+        let half_width = lanes_width / 2.0;
+        let shoulder = half_width + self.shoulder_width;
+        let curb = shoulder + self.curb_width;
+
+        let mut strips = Vec::new();
+        if self.curb_width > 0.0 {
+            strips.push(ProfileStrip {
+                left: (-curb, self.curb_height),
+                right: (-shoulder, 0.0),
+                color: CURB_COLOR,
+            });
+        }
+        if self.shoulder_width > 0.0 {
+            strips.push(ProfileStrip {
+                left: (-shoulder, 0.0),
+                right: (-half_width, 0.0),
+                color: SHOULDER_COLOR,
+            });
+        }
+        let lanes = self.lanes.max(1);
+        let lane_width = lanes_width / lanes as f32;
+        for lane in 0..lanes {
+            let left = -half_width + lane as f32 * lane_width;
+            strips.push(ProfileStrip {
+                left: (left, 0.0),
+                right: (left + lane_width, 0.0),
+                color: LANE_COLOR,
+            });
+        }
+        if self.shoulder_width > 0.0 {
+            strips.push(ProfileStrip {
+                left: (half_width, 0.0),
+                right: (shoulder, 0.0),
+                color: SHOULDER_COLOR,
+            });
+        }
+        if self.curb_width > 0.0 {
+            strips.push(ProfileStrip {
+                left: (shoulder, 0.0),
+                right: (curb, self.curb_height),
+                color: CURB_COLOR,
+            });
+        }
+        strips
+    }
+}
+
+/// Cross-sections about every `SAMPLE_SPACING` meters along a Catmull-Rom spline going
+/// through every control point, wrapping around when the spline is closed.
+pub fn sample_spline(points: &[RoadPoint], closed: bool) -> Vec<RoadSample> {
+    let segments = match points.len() {
+        0 | 1 => 0,
+        n if closed => n,
+        n => n - 1,
+    };
+    sample_segments(points, closed, 0..segments)
+}
+
+/// Cross-sections of some segments of an open spline, the segment `i` going from the
+/// point `i` to the next one. The distances start from the first segment.
+pub fn sample_spline_section(points: &[RoadPoint], segments: Range<usize>) -> Vec<RoadSample> {
+    sample_segments(points, false, segments)
+}
+
+fn sample_segments(points: &[RoadPoint], closed: bool, segments: Range<usize>) -> Vec<RoadSample> {
+    let n = points.len() as isize;
+    // Open splines repeat their end points
+    let point = |index: isize| {
+        let index = if closed { index.rem_euclid(n) } else { index.clamp(0, n - 1) };
+        &points[index as usize]
+    };
+
+    let mut samples: Vec<RoadSample> = Vec::new();
+    for segment in segments.clone() {
+        let i = segment as isize;
+        let [p0, p1, p2, p3] = [i - 1, i, i + 1, i + 2].map(point);
+        let [q0, q1, q2, q3] = [p0, p1, p2, p3].map(RoadPoint::position);
+
+        let steps = (q1.distance(q2) / SAMPLE_SPACING).ceil().max(1.0) as usize;
+        // Every segment starts where the previous one ended
+        let first = if segment == segments.start { 0 } else { 1 };
+        for step in first..=steps {
+            let t = step as f32 / steps as f32;
+            let position = catmull_rom(q0, q1, q2, q3, t);
+            let forward = catmull_rom_derivative(q0, q1, q2, q3, t).normalize_or_zero();
+            let distance =
+                samples.last().map_or(0.0, |last| last.distance + last.position.distance(position));
+            samples.push(RoadSample {
+                position,
+                forward,
+                width: p1.width + (p2.width - p1.width) * t,
+                banking: (p1.banking + (p2.banking - p1.banking) * t).to_radians(),
+                distance,
+            });
+        }
+    }
+
+    samples
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let (t2, t3) = (t * t, t * t * t);
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+fn catmull_rom_derivative(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    0.5 * ((p2 - p0)
+        + 2.0 * (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t
+        + 3.0 * (3.0 * p1 - p0 - 3.0 * p2 + p3) * t * t)
+}
+
+/// The lanes of a road for the cars driving along it, relative to the road entity.
+#[derive(Component, Debug, Clone)]
+pub struct RoadLanes {
+    pub samples: Vec<RoadSample>,
+    pub lanes: u32,
+    /// The last sample is back on the first one.
+    pub closed: bool,
+}
+
+impl RoadLanes {
+    /// The length of the road, along its spline.
+    pub fn length(&self) -> f32 {
+        self.samples.last().map_or(0.0, |sample| sample.distance)
+    }
+
+    /// The index of the sample nearest to a position relative to the road.
+    pub fn nearest(&self, position: Vec3) -> Option<usize> {
+        (0..self.samples.len()).min_by(|&a, &b| {
+            let distance = |index: usize| self.samples[index].position.distance_squared(position);
+            distance(a).total_cmp(&distance(b))
+        })
+    }
+
+    /// The index of the sample about `ahead` meters after the given one and how far it is,
+    /// stopping at the end of an open road.
+    pub fn index_ahead(&self, from: usize, ahead: f32) -> (usize, f32) {
+        let (mut index, mut travelled) = (from, 0.0);
+        while travelled < ahead {
+            let next = match index + 1 {
+                next if next < self.samples.len() => next,
+                // The first sample is where the last one is
+                _ if self.closed && self.samples.len() > 1 => 1,
+                _ => break,
+            };
+            travelled += self.samples[index].position.distance(self.samples[next].position);
+            index = next;
+        }
+        (index, travelled)
+    }
+}
+
+/// The road mesh, its matching collider and its lanes.
+pub struct RoadGeometry {
+    pub mesh: Mesh,
+    pub collider: Collider,
+    pub lanes: RoadLanes,
+}
+
+/// Sweeps the profile along the samples into a mesh with normals, UVs, tangents and
+/// the colors of its strips.
+pub fn build_road_geometry(
+    samples: &[RoadSample],
+    profile: &RoadProfile,
+) -> Result<RoadGeometry, Box<dyn Error>> {
+    if samples.len() < 2 {
+        return Err("the road needs at least two control points".into());
+    }
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+
+    let strip_count = profile.strips(samples[0].width).len();
+    for strip_index in 0..strip_count {
+        let first_vertex = positions.len() as u32;
+        for sample in samples {
+            let strip = &profile.strips(sample.width)[strip_index];
+            let (side, up) = (sample.right(), sample.up());
+            let [left_edge, right_edge] = [strip.left, strip.right]
+                .map(|(offset, height)| sample.position + side * offset + up * height);
+            let normal = (right_edge - left_edge).cross(sample.forward).normalize_or_zero();
+            let v = sample.distance / UV_LENGTH;
+            for (position, u) in [(left_edge, 0.0), (right_edge, 1.0)] {
+                positions.push(position.to_array());
+                normals.push(normal.to_array());
+                uvs.push([u, v]);
+                colors.push(strip.color.as_linear_rgba_f32());
+            }
+        }
+
+        for i in 0..samples.len() as u32 - 1 {
+            let left = first_vertex + i * 2;
+            let (right, next_left, next_right) = (left + 1, left + 2, left + 3);
+            indices.extend([left, right, next_left, right, next_right, next_left]);
+        }
+    }
+
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh.generate_tangents()?;
+
+    let collider = Collider::trimesh_from_mesh(&mesh).ok_or("could not build the road collider")?;
+    let (first, last) = (samples[0].position, samples[samples.len() - 1].position);
+    let lanes = RoadLanes {
+        samples: samples.to_vec(),
+        lanes: profile.lanes.max(1),
+        closed: first.distance(last) < SAMPLE_SPACING / 2.0,
+    };
+    Ok(RoadGeometry { mesh, collider, lanes })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lanes(positions: &[(f32, f32)], closed: bool) -> RoadLanes {
+        let mut distance = 0.0;
+        let mut previous = None;
+        let samples = positions
+            .iter()
+            .map(|&(x, z)| {
+                let position = Vec3::new(x, 0.0, z);
+                distance += previous.map_or(0.0, |p: Vec3| p.distance(position));
+                previous = Some(position);
+                RoadSample { position, forward: Vec3::NEG_Z, width: 8.0, banking: 0.0, distance }
+            })
+            .collect();
+        RoadLanes { samples, lanes: 2, closed }
+    }
+
+    #[test]
+    fn index_ahead_stops_at_the_end_of_an_open_road() {
+        let road = lanes(&[(0.0, 0.0), (0.0, 10.0), (0.0, 20.0), (0.0, 30.0), (0.0, 40.0)], false);
+        assert_eq!(road.index_ahead(0, 15.0), (2, 20.0));
+        assert_eq!(road.index_ahead(2, 100.0), (4, 20.0));
+        assert_eq!(road.index_ahead(4, 10.0), (4, 0.0));
+    }
+
+    #[test]
+    fn index_ahead_wraps_around_a_closed_road() {
+        let square = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0), (0.0, 0.0)];
+        let road = lanes(&square, true);
+        assert_eq!(road.length(), 40.0);
+        // The last sample is the first one, the next is the second
+        assert_eq!(road.index_ahead(3, 15.0), (1, 20.0));
+    }
+
+    #[test]
+    fn lane_offsets_from_the_left() {
+        let sample = lanes(&[(0.0, 0.0)], false).samples[0];
+        assert_eq!(sample.lane_offset(2, 0), -2.0);
+        assert_eq!(sample.lane_offset(2, 1), 2.0);
+        // Clamped to the last lane, and a road always has one
+        assert_eq!(sample.lane_offset(2, 5), 2.0);
+        assert_eq!(sample.lane_offset(0, 0), 0.0);
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::prelude::*;
+use bevy::utils::Duration;
+use bevy::window::{PresentMode, PrimaryWindow, WindowMode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::car_input::{CarControls, CarInput};
+use crate::floating_origin::FloatingOrigin;
+use crate::flow::{spawn_notice, SelectedCar};
+use crate::hud::{format_lap_time, HudSettings, LapTimer, SpeedUnit};
+use crate::map_registry::{MapRegistry, SelectedMap};
+use crate::replay::{Replay, ReplayFrame};
+use crate::score::EndlessScore;
+use crate::tuning::list_presets;
+use crate::{PlayerCar, Sun};
+
+/// The version of the save format, bumped with a new migration whenever it changes in a
+/// way the serde defaults can't absorb.
+pub const SAVE_VERSION: u32 = 1;
+
+/// Upgrades a save from the version of its index plus one to the next one.
+const MIGRATIONS: &[fn(&mut Value) -> Result<(), Box<dyn Error>>] = &[];
+
+// Every version but the first needs its migration
+const _: () = assert!(MIGRATIONS.len() + 1 == SAVE_VERSION as usize);
+
+/// How many runs of every endless map are kept.
+const MAX_HIGH_SCORES: usize = 10;
+
+const SAVE_FILE: &str = "save.json";
+const GHOSTS_DIRECTORY: &str = "ghosts";
+
+/// Where the save and the ghosts are kept, the platform data directory.
+pub fn save_directory() -> PathBuf {
+    dirs::data_dir().map_or_else(|| PathBuf::from("save"), |dir| dir.join("conveyor-belt"))
+}
+
+/// Everything kept from one session to the next.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SaveData {
+    pub version: u32,
+    /// The best runs of every endless map, by map name, the best first.
+    pub high_scores: BTreeMap<String, Vec<HighScore>>,
+    /// The best lap of every circuit, by map name.
+    pub best_laps: BTreeMap<String, BestLap>,
+    /// The tuning presets that can be raced with besides the default car.
+    pub unlocked_cars: BTreeSet<String>,
+    pub settings: Settings,
+}
+
+impl Default for SaveData {
+    fn default() -> SaveData {
+        SaveData {
+            version: SAVE_VERSION,
+            high_scores: default(),
+            best_laps: default(),
+            unlocked_cars: default(),
+            settings: default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HighScore {
+    pub points: f32,
+    /// The tuning preset raced with, the default car when there is none.
+    pub car: Option<String>,
+    /// When the run ended, in seconds since the Unix epoch.
+    pub date: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BestLap {
+    pub time: Duration,
+    pub car: Option<String>,
+    /// The replay of the race up to the end of the lap, in the ghosts directory, from the
+    /// spawn point like the recorded ones.
+    pub ghost: Option<String>,
+    /// The frame of the ghost where the lap starts, the ones before bring the car there.
+    #[serde(default)]
+    pub lap_start: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    pub graphics: GraphicsSettings,
+    pub controls: CarControls,
+    pub unit: SpeedUnit,
+}
+
+/// Changed in the settings menu and stored in the save right away.
+#[derive(Resource, Reflect, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[reflect(Resource)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    pub shadows: bool,
+    pub vsync: bool,
+    pub fullscreen: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> GraphicsSettings {
+        GraphicsSettings { shadows: true, vsync: true, fullscreen: false }
+    }
+}
+
+impl SaveData {
+    /// Adds the run to the high scores of the map, returns whether it is the best one.
+    pub fn add_high_score(&mut self, map: &str, score: HighScore) -> bool {
+        let scores = self.high_scores.entry(map.to_string()).or_default();
+        let rank = scores.iter().take_while(|high| high.points >= score.points).count();
+        scores.insert(rank, score);
+        scores.truncate(MAX_HIGH_SCORES);
+        rank == 0
+    }
+
+    pub fn high_score(&self, map: &str) -> Option<&HighScore> {
+        self.high_scores.get(map).and_then(|scores| scores.first())
+    }
+
+    /// Unlocks the first locked preset of the list.
+    pub fn unlock_next_car(&mut self, presets: &[String]) -> Option<String> {
+        let next = presets.iter().find(|preset| !self.unlocked_cars.contains(*preset))?;
+        self.unlocked_cars.insert(next.clone());
+        Some(next.clone())
+    }
+
+    /// The save of a new player, the presets already there when the locks came are unlocked.
+    fn first() -> SaveData {
+        SaveData { unlocked_cars: list_presets().into_iter().collect(), ..default() }
+    }
+
+    /// Reads a save of any version up to the current one.
+    fn parse(text: &str) -> Result<SaveData, Box<dyn Error>> {
+        let mut value: Value = serde_json::from_str(text)?;
+        let version = value
+            .get("version")
+            .and_then(Value::as_u64)
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|&version| version >= 1)
+            .ok_or("the save has no valid version")?;
+        if version > SAVE_VERSION {
+            return Err(Box::new(NewerVersion(version)));
+        }
+        for migration in &MIGRATIONS[version as usize - 1..] {
+            migration(&mut value)?;
+        }
+        value["version"] = SAVE_VERSION.into();
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+/// A save written by a newer version of the game, it is left as is.
+#[derive(Debug)]
+struct NewerVersion(u32);
+
+impl std::fmt::Display for NewerVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the save is from a newer version of the game (version {})", self.0)
+    }
+}
+
+impl Error for NewerVersion {}
+
+/// The save data and the file it is written to as soon as it changes.
+#[derive(Resource)]
+pub struct SaveFile {
+    directory: PathBuf,
+    pub data: SaveData,
+    /// Set when the save on disk must not be overwritten.
+    read_only: bool,
+    /// What went wrong while loading, shown on the main menu.
+    problem: Option<String>,
+}
+
+impl SaveFile {
+    /// Loads the save of the directory, the backup of the last one when it is corrupt and
+    /// the defaults when both are.
+    ///
+    /// It runs before the app and its logger start, the problems go straight to stderr and
+    /// are shown again on the main menu.
+    pub fn load(directory: PathBuf) -> SaveFile {
+        let path = directory.join(SAVE_FILE);
+        let mut save =
+            SaveFile { directory, data: SaveData::first(), read_only: false, problem: None };
+
+        let error = match fs::read_to_string(&path) {
+            Ok(text) => match SaveData::parse(&text) {
+                Ok(data) => {
+                    eprintln!("loaded the save from {}", path.display());
+                    save.data = data;
+                    return save;
+                }
+                Err(e) => e,
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // Written right away, the presets added later are locked
+                save.store();
+                return save;
+            }
+            Err(e) => {
+                // It may be fine, only unreadable for now
+                save.read_only = true;
+                save.report(format!("could not read the save at {}: {e}", path.display()));
+                return save;
+            }
+        };
+        if error.is::<NewerVersion>() {
+            save.read_only = true;
+            save.report(format!("{error}, progress won't be saved"));
+            return save;
+        }
+
+        // Move the corrupt save aside, the next write would replace it
+        let corrupt = path.with_extension(format!("json.corrupt-{}", unix_time()));
+        if let Err(e) = fs::rename(&path, &corrupt) {
+            save.read_only = true;
+            save.report(format!("the save is corrupt ({error}) and could not be moved: {e}"));
+            return save;
+        }
+
+        let backup = path.with_extension("json.bak");
+        match fs::read_to_string(&backup)
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|t| SaveData::parse(&t))
+        {
+            Ok(data) => {
+                save.data = data;
+                save.report(format!(
+                    "the save is corrupt ({error}), restored the previous one, moved it to {}",
+                    corrupt.display()
+                ));
+            }
+            Err(_) => save.report(format!(
+                "the save is corrupt ({error}), starting over, moved it to {}",
+                corrupt.display()
+            )),
+        }
+        save
+    }
+
+    fn report(&mut self, problem: String) {
+        eprintln!("{problem}");
+        self.problem = Some(problem);
+    }
+
+    /// Writes the save, keeping the previous one as a backup.
+    ///
+    /// It goes to a temporary file first, so that a crash while writing never leaves a
+    /// half written save.
+    fn write(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.directory)?;
+        let path = self.directory.join(SAVE_FILE);
+        let temporary = path.with_extension("json.tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(&serde_json::to_vec_pretty(&self.data)?)?;
+        file.sync_all()?;
+        if path.exists() {
+            fs::copy(&path, path.with_extension("json.bak"))?;
+        }
+        fs::rename(&temporary, &path)
+    }
+
+    /// Writes the save now, all the changes are kept right away.
+    pub fn store(&self) {
+        if self.read_only {
+            return;
+        }
+        if let Err(e) = self.write() {
+            error!("could not write the save to {}: {e}", self.directory.display());
+        }
+    }
+
+    pub fn ghost_path(&self, file_name: &str) -> PathBuf {
+        self.directory.join(GHOSTS_DIRECTORY).join(file_name)
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+/// A file name for the map, whatever its name is made of.
+fn ghost_file_name(map: &str) -> String {
+    let stem: String =
+        map.chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' }).collect();
+    format!("{stem}.bin")
+}
+
+/// Shows what went wrong while loading the save.
+pub fn report_save_problem(mut commands: Commands, mut save: ResMut<SaveFile>) {
+    if let Some(problem) = save.problem.take() {
+        spawn_notice(&mut commands, problem);
+    }
+}
+
+/// Applies the graphics settings to the window and the sun of the race.
+pub fn apply_graphics_settings(
+    graphics: Res<GraphicsSettings>,
+    mut windows_q: Query<&mut Window, With<PrimaryWindow>>,
+    mut suns_q: Query<&mut DirectionalLight, With<Sun>>,
+) {
+    if let Ok(mut window) = windows_q.get_single_mut() {
+        let present_mode =
+            if graphics.vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+        let mode = if graphics.fullscreen {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        };
+        if window.present_mode != present_mode || window.mode != mode {
+            window.present_mode = present_mode;
+            window.mode = mode;
+        }
+    }
+    for mut sun in &mut suns_q {
+        sun.shadows_enabled = graphics.shadows;
+    }
+}
+
+/// Keeps the speed unit switched on the dashboard and the graphics and controls of the
+/// settings menu.
+pub fn store_settings(
+    hud_settings: Res<HudSettings>,
+    graphics: Res<GraphicsSettings>,
+    controls: Res<CarControls>,
+    mut save: ResMut<SaveFile>,
+) {
+    let settings = Settings {
+        graphics: graphics.clone(),
+        controls: controls.clone(),
+        unit: hud_settings.unit,
+    };
+    if save.data.settings != settings {
+        save.data.settings = settings;
+        save.store();
+    }
+}
+
+/// Records the inputs of the race to keep the best lap as a ghost.
+#[derive(Resource, Default)]
+pub struct GhostRecorder {
+    replay: Replay,
+    /// The laps completed when the last one was checked.
+    laps: usize,
+    /// The frame where the current lap started.
+    lap_start: usize,
+}
+
+pub fn start_ghost_recording(
+    mut recorder: ResMut<GhostRecorder>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+    selected_car: Res<SelectedCar>,
+) {
+    let replay = Replay {
+        map: registry.maps[selected_map.0].name.clone(),
+        car: selected_car.0.clone(),
+        ..default()
+    };
+    *recorder = GhostRecorder { replay, ..default() };
+}
+
+pub fn record_ghost_frame(
+    time: Res<Time>,
+    mut recorder: ResMut<GhostRecorder>,
+    car_q: Query<&CarInput, With<PlayerCar>>,
+) {
+    let Ok(&input) = car_q.get_single() else { return };
+    recorder.replay.frames.push(ReplayFrame { delta: time.delta(), input });
+}
+
+/// Keeps the lap just completed when it beats the best one of the map, with its ghost,
+/// and unlocks a car with it.
+pub fn record_best_lap(
+    mut commands: Commands,
+    mut save: ResMut<SaveFile>,
+    mut recorder: ResMut<GhostRecorder>,
+    lap_timer: Res<LapTimer>,
+    origin: Res<FloatingOrigin>,
+    car_q: Query<&Transform, With<PlayerCar>>,
+) {
+    if lap_timer.laps() == recorder.laps {
+        return;
+    }
+    recorder.laps = lap_timer.laps();
+    let frames = recorder.replay.frames.len();
+    let lap_start = std::mem::replace(&mut recorder.lap_start, frames);
+    let Some(time) = lap_timer.last_lap() else { return };
+    let map = recorder.replay.map.clone();
+    if save.data.best_laps.get(&map).map_or(false, |best| best.time <= time) {
+        return;
+    }
+
+    let mut ghost = None;
+    if let Ok(car_transform) = car_q.get_single() {
+        let replay = &mut recorder.replay;
+        replay.final_translation = origin.absolute(car_transform.translation).as_vec3().to_array();
+        replay.final_rotation = car_transform.rotation.to_array();
+        let file_name = ghost_file_name(&map);
+        let path = save.ghost_path(&file_name);
+        let saved = fs::create_dir_all(save.directory.join(GHOSTS_DIRECTORY))
+            .map_err(bincode::Error::from)
+            .and_then(|()| replay.save(&path));
+        match saved {
+            Ok(()) => ghost = Some(file_name),
+            Err(e) => error!("could not save the ghost to {}: {e}", path.display()),
+        }
+    }
+
+    info!("new best lap on {map}: {}", format_lap_time(time));
+    let car = recorder.replay.car.clone();
+    save.data.best_laps.insert(map, BestLap { time, car, ghost, lap_start });
+    if let Some(car) = save.data.unlock_next_car(&list_presets()) {
+        spawn_notice(&mut commands, format!("new best lap, unlocked {car}"));
+    }
+    save.store();
+}
+
+/// Adds the run of the endless road to the high scores, a new best unlocking a car.
+pub fn record_high_score(
+    mut commands: Commands,
+    mut save: ResMut<SaveFile>,
+    score: Option<Res<EndlessScore>>,
+    registry: Res<MapRegistry>,
+    selected_map: Res<SelectedMap>,
+    selected_car: Res<SelectedCar>,
+) {
+    let Some(score) = score else { return };
+    let map = &registry.maps[selected_map.0].name;
+    let high_score =
+        HighScore { points: score.points, car: selected_car.0.clone(), date: unix_time() };
+    if save.data.add_high_score(map, high_score) {
+        if let Some(car) = save.data.unlock_next_car(&list_presets()) {
+            spawn_notice(&mut commands, format!("new high score, unlocked {car}"));
+        }
+    }
+    save.store();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(points: f32) -> HighScore {
+        HighScore { points, car: None, date: 0 }
+    }
+
+    /// An empty directory of its own for every test.
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("conveyor-belt-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn parses_the_current_version() {
+        let mut data = SaveData::default();
+        data.add_high_score("Endless", score(42.0));
+        let parsed = SaveData::parse(&serde_json::to_string(&data).unwrap()).unwrap();
+        assert_eq!(parsed.version, SAVE_VERSION);
+        assert_eq!(parsed.high_score("Endless").unwrap().points, 42.0);
+    }
+
+    #[test]
+    fn rejects_a_newer_version() {
+        let text = format!("{{\"version\": {}}}", SAVE_VERSION + 1);
+        let error = SaveData::parse(&text).unwrap_err();
+        assert!(error.is::<NewerVersion>());
+    }
+
+    #[test]
+    fn rejects_a_missing_version() {
+        for text in ["{}", "{\"version\": 0}", "{\"version\": \"1\"}"] {
+            let error = SaveData::parse(text).unwrap_err();
+            assert!(!error.is::<NewerVersion>());
+        }
+    }
+
+    #[test]
+    fn restores_the_backup_of_a_corrupt_save() {
+        let directory = temporary_directory("corrupt");
+        let mut backup = SaveData::default();
+        backup.add_high_score("Endless", score(42.0));
+        fs::write(directory.join(SAVE_FILE), "{\"version\": 1, \"high_sc").unwrap();
+        fs::write(directory.join("save.json.bak"), serde_json::to_string(&backup).unwrap())
+            .unwrap();
+
+        let save = SaveFile::load(directory.clone());
+        assert_eq!(save.data.high_score("Endless").unwrap().points, 42.0);
+        assert!(!save.read_only);
+        assert!(save.problem.is_some());
+        assert!(!directory.join(SAVE_FILE).exists());
+        let moved = fs::read_dir(&directory)
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.file_name().to_string_lossy().starts_with("save.json.corrupt-"));
+        assert!(moved);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn ranks_and_truncates_the_high_scores() {
+        let mut data = SaveData::default();
+        assert!(data.add_high_score("Endless", score(10.0)));
+        assert!(!data.add_high_score("Endless", score(5.0)));
+        assert!(data.add_high_score("Endless", score(20.0)));
+        // A tie goes after the score it matches
+        assert!(!data.add_high_score("Endless", score(20.0)));
+        for points in 0..MAX_HIGH_SCORES {
+            data.add_high_score("Endless", score(points as f32 / 10.0));
+        }
+
+        let points: Vec<_> = data.high_scores["Endless"].iter().map(|high| high.points).collect();
+        assert_eq!(points.len(), MAX_HIGH_SCORES);
+        assert_eq!(&points[..4], &[20.0, 20.0, 10.0, 5.0]);
+        assert!(points.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(data.high_scores.get("Circuit").is_none());
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::{HashMap, HashSet};
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+
+use crate::car_collision::CarCollision;
+use crate::drift::Drift;
+use crate::endless::EndlessRoad;
+use crate::telemetry::WheelTelemetry;
+use crate::traffic::TrafficCar;
+use crate::{GameState, PlayerCar, RaceEntity};
+
+/// The points for every meter of road driven.
+const DISTANCE_POINTS: f32 = 1.0;
+
+/// How far from the player, to its sides and along it, the near miss sensor reaches.
+const SENSOR_HALF_WIDTH: f32 = 3.5;
+const SENSOR_HALF_LENGTH: f32 = 4.0;
+
+/// How far from the middle of a car its sides are.
+const CAR_HALF_WIDTH: f32 = 1.0;
+
+/// The points of a near miss, doubled when brushing the other car.
+const NEAR_MISS_POINTS: f32 = 100.0;
+
+/// How fast, in m/s, the player must drive for the near misses to count.
+const NEAR_MISS_MIN_SPEED: f32 = 15.0;
+
+/// How long the car must fly to score, and the points for every second of airtime.
+const AIRTIME_MIN_TIME: f32 = 0.5;
+const AIRTIME_POINTS: f32 = 100.0;
+
+/// How long a combo lasts after its last trick.
+const COMBO_TIME: f32 = 4.0;
+
+/// How much every trick of a combo adds to the multiplier, up to the maximum.
+const COMBO_STEP: f32 = 0.5;
+const MAX_MULTIPLIER: f32 = 5.0;
+
+/// An impact changing the velocity of the car by this much, in m/s, ends the run.
+const CRASH_VELOCITY_CHANGE: f32 = 12.0;
+
+/// How far beside the lanes, in meters, the car is off the road, and for how long it can be.
+const OFF_ROAD_MARGIN: f32 = 3.0;
+const OFF_ROAD_TIME: f32 = 2.0;
+
+/// How far below the road, in meters, the car has fallen off it.
+const FALL_HEIGHT: f32 = 10.0;
+
+/// Why a run on the endless road ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameOver {
+    Crash,
+    OffRoad,
+}
+
+/// The score of a run on the endless road.
+///
+/// Its times are the ones of the physics, which stop with it in the photo mode and the
+/// road editor.
+#[derive(Resource, Default, Debug)]
+pub struct EndlessScore {
+    pub points: f32,
+    /// The number of tricks of the current combo.
+    pub combo: u32,
+    combo_until: f32,
+    scored_distance: f32,
+    /// The traffic cars in the near miss sensor, with the smallest gap to their side.
+    nearby: HashMap<Entity, f32>,
+    /// The traffic cars that touched the player, they don't count until out of the sensor.
+    touched: HashSet<Entity>,
+    airborne_since: Option<f32>,
+    off_road_since: Option<f32>,
+    /// The last trick scored, with its points.
+    pub last_trick: Option<(String, f32)>,
+    pub game_over: Option<GameOver>,
+}
+
+impl EndlessScore {
+    pub fn multiplier(&self) -> f32 {
+        (1.0 + self.combo as f32 * COMBO_STEP).min(MAX_MULTIPLIER)
+    }
+
+    /// Scores a trick with the multiplier of the combo, which it extends.
+    pub fn award(&mut self, now: f32, trick: &str, points: f32) {
+        let points = points * self.multiplier();
+        self.points += points;
+        self.combo += 1;
+        self.combo_until = now + COMBO_TIME;
+        self.last_trick = Some((trick.to_string(), points));
+        info!("{trick}: {points:.0} points");
+    }
+}
+
+/// Scores the distance and ends the combos without a new trick.
+pub fn score_distance(
+    time: Res<Time<Physics>>,
+    road: Res<EndlessRoad>,
+    mut score: ResMut<EndlessScore>,
+) {
+    if score.game_over.is_some() {
+        return;
+    }
+    let driven = road.furthest - score.scored_distance;
+    if driven > 0.0 {
+        score.points += driven * DISTANCE_POINTS;
+        score.scored_distance = road.furthest;
+    }
+    if score.combo > 0 && time.elapsed_seconds() > score.combo_until {
+        score.combo = 0;
+    }
+}
+
+/// Scores the traffic cars passing close to the player in its sensor without touching it.
+pub fn score_near_misses(
+    time: Res<Time<Physics>>,
+    mut score: ResMut<EndlessScore>,
+    mut collisions: EventReader<CarCollision>,
+    player_q: Query<(Entity, &Transform, &LinearVelocity), With<PlayerCar>>,
+    traffic_q: Query<(Entity, &Transform), With<TrafficCar>>,
+) {
+    let Ok((player, player_transform, &LinearVelocity(lin_vel))) = player_q.get_single() else {
+        return;
+    };
+    // Touching is not missing
+    for collision in collisions.read() {
+        if collision.car == player {
+            score.nearby.remove(&collision.other);
+            score.touched.insert(collision.other);
+        }
+    }
+
+    let to_local = player_transform.compute_affine().inverse();
+    let speed = player_transform.forward().dot(lin_vel);
+    for (car, transform) in &traffic_q {
+        let local = to_local.transform_point3(transform.translation);
+        let gap = (local.x.abs() - 2.0 * CAR_HALF_WIDTH).max(0.0);
+        if local.x.abs() < SENSOR_HALF_WIDTH && local.z.abs() < SENSOR_HALF_LENGTH {
+            if !score.touched.contains(&car) {
+                let closest = score.nearby.entry(car).or_insert(gap);
+                *closest = closest.min(gap);
+            }
+            continue;
+        }
+        score.touched.remove(&car);
+        if let Some(closest) = score.nearby.remove(&car) {
+            if speed >= NEAR_MISS_MIN_SPEED && score.game_over.is_none() {
+                let sensor_gap = SENSOR_HALF_WIDTH - 2.0 * CAR_HALF_WIDTH;
+                let closeness = 1.0 - (closest / sensor_gap).min(1.0);
+                score.award(
+                    time.elapsed_seconds(),
+                    "near miss",
+                    NEAR_MISS_POINTS * (1.0 + closeness),
+                );
+            }
+        }
+    }
+    score.nearby.retain(|&car, _| traffic_q.contains(car));
+    score.touched.retain(|&car| traffic_q.contains(car));
+}
+
+/// Scores the airtime, from when the wheels leave the ground to the landing.
+pub fn score_airtime(
+    time: Res<Time<Physics>>,
+    mut score: ResMut<EndlessScore>,
+    player_q: Query<Entity, With<PlayerCar>>,
+    telemetry_q: Query<(&Parent, &WheelTelemetry)>,
+) {
+    let Ok(player) = player_q.get_single() else { return };
+    if score.game_over.is_some() {
+        return;
+    }
+    let now = time.elapsed_seconds();
+    let wheels: Vec<_> = telemetry_q
+        .iter()
+        .filter(|(parent, _)| parent.get() == player)
+        .map(|(_, telemetry)| telemetry)
+        .collect();
+    if wheels.is_empty() {
+        return;
+    }
+
+    let airborne = wheels.iter().all(|telemetry| telemetry.compression <= 0.0);
+    match (airborne, score.airborne_since) {
+        (true, None) => score.airborne_since = Some(now),
+        (false, Some(since)) => {
+            score.airborne_since = None;
+            let duration = now - since;
+            if duration >= AIRTIME_MIN_TIME {
+                score.award(now, "airtime", duration * AIRTIME_POINTS);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Ends the run when the player crashes or leaves the road.
+pub fn detect_game_over(
+    time: Res<Time<Physics>>,
+    mut score: ResMut<EndlessScore>,
+    mut next_state: ResMut<NextState<GameState>>,
+    mut collisions: EventReader<CarCollision>,
+    road: Res<EndlessRoad>,
+    player_q: Query<(Entity, &Transform), With<PlayerCar>>,
+) {
+    let Ok((player, transform)) = player_q.get_single() else { return };
+    if score.game_over.is_some() {
+        return;
+    }
+    let now = time.elapsed_seconds();
+
+    // Landing hard is not a crash
+    let crashed = collisions.read().any(|collision| {
+        collision.car == player
+            && collision.velocity_change >= CRASH_VELOCITY_CHANGE
+            && collision.direction.y > -0.7
+    });
+
+    let position = transform.translation;
+    let nearest = road.nearest_sample(position);
+    let off_road = nearest.map_or(true, |sample| {
+        let relative = position - sample.position;
+        relative.dot(sample.right()).abs() > sample.width / 2.0 + OFF_ROAD_MARGIN
+    });
+    let fallen = nearest.map_or(false, |sample| sample.position.y - position.y > FALL_HEIGHT);
+    if off_road {
+        score.off_road_since.get_or_insert(now);
+    } else {
+        score.off_road_since = None;
+    }
+    let off_road_too_long = score.off_road_since.map_or(false, |since| now - since > OFF_ROAD_TIME);
+
+    let game_over = if crashed {
+        GameOver::Crash
+    } else if fallen || off_road_too_long {
+        GameOver::OffRoad
+    } else {
+        return;
+    };
+    info!("game over: {game_over:?} with {:.0} points", score.points);
+    score.game_over = Some(game_over);
+    next_state.set(GameState::Results);
+}
+
+#[derive(Component)]
+pub enum ScoreText {
+    Points,
+    Combo,
+    Drift,
+    LastTrick,
+}
+
+/// Shows the score at the top of the screen.
+pub fn spawn_score_display(mut commands: Commands, texts_q: Query<(), With<ScoreText>>) {
+    if !texts_q.is_empty() {
+        return;
+    }
+    let text = |size: f32, color: Color| TextStyle { font_size: size, color, ..default() };
+    commands
+        .spawn((
+            RaceEntity,
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    width: Val::Percent(100.0),
+                    top: Val::Px(10.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    ..default()
+                },
+                ..default()
+            },
+        ))
+        .with_children(|root| {
+            for (score_text, size, color) in [
+                (ScoreText::Points, 32.0, Color::WHITE),
+                (ScoreText::Combo, 20.0, Color::ORANGE_RED),
+                (ScoreText::Drift, 20.0, Color::YELLOW),
+                (ScoreText::LastTrick, 16.0, Color::GRAY),
+            ] {
+                root.spawn((TextBundle::from_section("", text(size, color)), score_text));
+            }
+        });
+}
+
+pub fn update_score_display(
+    score: Res<EndlessScore>,
+    drift_q: Query<&Drift, With<PlayerCar>>,
+    mut texts_q: Query<(&ScoreText, &mut Text)>,
+) {
+    let drift = drift_q.get_single().ok();
+    for (score_text, mut text) in &mut texts_q {
+        text.sections[0].value = match score_text {
+            ScoreText::Points => format!("{:.0}", score.points),
+            ScoreText::Combo if score.combo > 0 => format!("combo x{:.1}", score.multiplier()),
+            ScoreText::Combo => String::new(),
+            ScoreText::Drift => match drift {
+                Some(drift @ Drift { current: Some(current), .. }) => format!(
+                    "drift {:.0}° {:.0} x{:.2}",
+                    current.measures.mean_angle,
+                    current.points(),
+                    drift.chain_multiplier()
+                ),
+                _ => String::new(),
+            },
+            ScoreText::LastTrick => score
+                .last_trick
+                .as_ref()
+                .map_or_else(String::new, |(trick, points)| format!("{trick} +{points:.0}")),
+        };
+    }
+}
@@ -0,0 +1,259 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::Serialize;
+
+use crate::car_input::CarInput;
+use crate::car_suspension::CarPhysics;
+use crate::{CarWheel, PlayerCar, RayCastWheelEntity};
+
+/// Intermediate values computed by the vehicle systems for a single wheel.
+///
+/// Associated to the RayCaster of the wheel and updated every frame.
+#[derive(Component, Reflect, Serialize, Debug, Default, Clone, Copy)]
+pub struct WheelTelemetry {
+    /// How much the suspension is compressed, from 0 (extended) to 1 (fully compressed).
+    pub compression: f32,
+    pub spring_force: f32,
+    /// The velocity of the tire in the steering direction, the one the grip fights against.
+    pub lateral_velocity: f32,
+    pub grip_factor: f32,
+    pub lateral_force: f32,
+    pub accel_force: f32,
+}
+
+#[derive(Reflect, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TelemetryFormat {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+impl TelemetryFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            TelemetryFormat::Csv => "csv",
+            TelemetryFormat::JsonLines => "jsonl",
+        }
+    }
+}
+
+/// Tweakable from the inspector, changes are used by the next capture.
+#[derive(Resource, Reflect, Default)]
+#[reflect(Resource)]
+pub struct TelemetrySettings {
+    pub format: TelemetryFormat,
+}
+
+/// The telemetry capture in progress, if any.
+#[derive(Resource, Default)]
+pub struct TelemetryRecorder {
+    capture: Option<TelemetryCapture>,
+}
+
+struct TelemetryCapture {
+    path: PathBuf,
+    format: TelemetryFormat,
+    writer: BufWriter<File>,
+    samples: usize,
+}
+
+#[derive(Serialize)]
+struct TelemetrySample {
+    time: f32,
+    /// Signed forward speed in m/s.
+    speed: f32,
+    input: CarInput,
+    wheel_rotation: f32,
+    wheels: Vec<WheelSample>,
+}
+
+#[derive(Serialize)]
+struct WheelSample {
+    wheel: &'static str,
+    #[serde(flatten)]
+    telemetry: WheelTelemetry,
+}
+
+const WHEEL_COLUMNS: [&str; 6] = [
+    "compression",
+    "spring_force",
+    "lateral_velocity",
+    "grip_factor",
+    "lateral_force",
+    "accel_force",
+];
+
+/// The wheels in the order [`collect_wheels_telemetry`] returns them.
+pub const WHEELS: [CarWheel; 4] =
+    [CarWheel::FrontRight, CarWheel::FrontLeft, CarWheel::BackRight, CarWheel::BackLeft];
+
+pub fn wheel_label(wheel: CarWheel) -> &'static str {
+    match wheel {
+        CarWheel::FrontRight => "front_right",
+        CarWheel::FrontLeft => "front_left",
+        CarWheel::BackRight => "back_right",
+        CarWheel::BackLeft => "back_left",
+    }
+}
+
+/// Collects the telemetry of the four wheels of the car, sorted like [`WHEELS`].
+///
+/// Returns `None` until every wheel of the car has been identified.
+pub fn collect_wheels_telemetry(
+    car: Entity,
+    wheels_query: &Query<&CarWheel>,
+    raycast_query: &Query<(&Parent, &RayCastWheelEntity, &WheelTelemetry)>,
+) -> Option<[(CarWheel, WheelTelemetry); 4]> {
+    let mut wheels: Vec<_> = raycast_query
+        .iter()
+        .filter(|(parent, _, _)| parent.get() == car)
+        .filter_map(|(_, &RayCastWheelEntity(entity), &telemetry)| {
+            wheels_query.get(entity).ok().map(|&wheel| (wheel, telemetry))
+        })
+        .collect();
+    wheels.sort_unstable_by_key(|(wheel, _)| *wheel);
+    wheels.try_into().ok()
+}
+
+impl TelemetryCapture {
+    fn create(format: TelemetryFormat) -> io::Result<TelemetryCapture> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let path = PathBuf::from(format!("telemetry-{timestamp}.{}", format.extension()));
+        let mut writer = BufWriter::new(File::create(&path)?);
+
+        if format == TelemetryFormat::Csv {
+            write!(writer, "time,speed,accelerate,reverse,steer_left,steer_right,wheel_rotation")?;
+            for wheel in WHEELS {
+                for column in WHEEL_COLUMNS {
+                    write!(writer, ",{}_{column}", wheel_label(wheel))?;
+                }
+            }
+            writeln!(writer)?;
+        }
+
+        Ok(TelemetryCapture { path, format, writer, samples: 0 })
+    }
+
+    /// Flushes what is left of the capture to its file.
+    fn finish(mut self) {
+        match self.writer.flush() {
+            Ok(()) => info!("saved {} telemetry samples to {}", self.samples, self.path.display()),
+            Err(e) => error!("could not save the telemetry to {}: {e}", self.path.display()),
+        }
+    }
+
+    fn write_sample(&mut self, sample: &TelemetrySample) -> io::Result<()> {
+        match self.format {
+            TelemetryFormat::Csv => {
+                let TelemetrySample { time, speed, input, wheel_rotation, wheels } = sample;
+                write!(
+                    self.writer,
+                    "{time},{speed},{},{},{},{},{wheel_rotation}",
+                    input.accelerate as u8,
+                    input.reverse as u8,
+                    input.steer_left as u8,
+                    input.steer_right as u8,
+                )?;
+                for WheelSample { telemetry, .. } in wheels {
+                    let WheelTelemetry {
+                        compression,
+                        spring_force,
+                        lateral_velocity,
+                        grip_factor,
+                        lateral_force,
+                        accel_force,
+                    } = telemetry;
+                    write!(self.writer, ",{compression},{spring_force},{lateral_velocity}")?;
+                    write!(self.writer, ",{grip_factor},{lateral_force},{accel_force}")?;
+                }
+                writeln!(self.writer)?;
+            }
+            TelemetryFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, sample)?;
+                writeln!(self.writer)?;
+            }
+        }
+
+        self.samples += 1;
+        Ok(())
+    }
+}
+
+/// Writes what is left of the capture in progress when the app exits.
+pub fn finish_telemetry_capture_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut recorder: ResMut<TelemetryRecorder>,
+) {
+    if exit_events.read().last().is_none() {
+        return;
+    }
+    if let Some(capture) = recorder.capture.take() {
+        capture.finish();
+    }
+}
+
+pub fn toggle_telemetry_capture(
+    keys: Res<Input<KeyCode>>,
+    settings: Res<TelemetrySettings>,
+    mut recorder: ResMut<TelemetryRecorder>,
+) {
+    if !keys.just_pressed(KeyCode::F3) {
+        return;
+    }
+
+    match recorder.capture.take() {
+        Some(capture) => capture.finish(),
+        None => match TelemetryCapture::create(settings.format) {
+            Ok(capture) => {
+                info!("capturing telemetry to {}", capture.path.display());
+                recorder.capture = Some(capture);
+            }
+            Err(e) => error!("could not start the telemetry capture: {e}"),
+        },
+    }
+}
+
+pub fn record_telemetry_sample(
+    time: Res<Time>,
+    mut recorder: ResMut<TelemetryRecorder>,
+    car_query: Query<
+        (Entity, &CarPhysics, &CarInput, &LinearVelocity, &Transform),
+        With<PlayerCar>,
+    >,
+    wheels_query: Query<&CarWheel>,
+    raycast_query: Query<(&Parent, &RayCastWheelEntity, &WheelTelemetry)>,
+) {
+    let Some(capture) = recorder.capture.as_mut() else { return };
+    let Ok((car, car_physics, &input, &LinearVelocity(lin_vel), car_transform)) =
+        car_query.get_single()
+    else {
+        return;
+    };
+
+    // The CSV header expects every wheel to be there
+    let Some(wheels) = collect_wheels_telemetry(car, &wheels_query, &raycast_query) else {
+        return;
+    };
+
+    let sample = TelemetrySample {
+        time: time.elapsed_seconds(),
+        speed: car_transform.forward().dot(lin_vel),
+        input,
+        wheel_rotation: car_physics.wheel_rotation,
+        wheels: wheels
+            .into_iter()
+            .map(|(wheel, telemetry)| WheelSample { wheel: wheel_label(wheel), telemetry })
+            .collect(),
+    };
+
+    if let Err(e) = capture.write_sample(&sample) {
+        error!("stopping the telemetry capture to {}: {e}", capture.path.display());
+        recorder.capture = None;
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_inspector_egui::bevy_egui::EguiContexts;
+use bevy_inspector_egui::egui::{self, Align2, Color32, FontId, Pos2, Shape, Stroke};
+use bevy_xpbd_3d::prelude::*;
+
+use crate::car_suspension::CarPhysics;
+use crate::telemetry::{collect_wheels_telemetry, wheel_label, WheelTelemetry, WHEELS};
+use crate::{CarWheel, PlayerCar, RayCastWheelEntity};
+
+/// How many seconds of history the graphs show.
+const HISTORY_DURATION: f32 = 10.0;
+
+/// How many seconds of history the friction circles trail.
+const FRICTION_TRAIL_DURATION: f32 = 0.5;
+
+const GRAPH_SIZE: egui::Vec2 = egui::vec2(400.0, 70.0);
+const FRICTION_CIRCLE_SIZE: f32 = 90.0;
+
+/// One color per wheel, in the order of [`WHEELS`].
+const WHEEL_COLORS: [Color32; 4] = [
+    Color32::from_rgb(230, 80, 80),
+    Color32::from_rgb(80, 160, 230),
+    Color32::from_rgb(230, 180, 60),
+    Color32::from_rgb(110, 200, 110),
+];
+
+/// Live scrolling graphs of the car telemetry, toggled with F4.
+#[derive(Resource, Default)]
+pub struct TelemetryOverlay {
+    visible: bool,
+    history: VecDeque<OverlaySample>,
+}
+
+#[derive(Clone, Copy)]
+struct OverlaySample {
+    time: f32,
+    /// Signed forward speed in km/h.
+    speed: f32,
+    wheel_rotation: f32,
+    wheels: [WheelTelemetry; 4],
+}
+
+pub fn toggle_telemetry_overlay(keys: Res<Input<KeyCode>>, mut overlay: ResMut<TelemetryOverlay>) {
+    if keys.just_pressed(KeyCode::F4) {
+        overlay.visible = !overlay.visible;
+    }
+}
+
+pub fn sample_telemetry_overlay(
+    time: Res<Time>,
+    mut overlay: ResMut<TelemetryOverlay>,
+    car_query: Query<(Entity, &CarPhysics, &LinearVelocity, &Transform), With<PlayerCar>>,
+    wheels_query: Query<&CarWheel>,
+    raycast_query: Query<(&Parent, &RayCastWheelEntity, &WheelTelemetry)>,
+) {
+    let Ok((car, car_physics, &LinearVelocity(lin_vel), car_transform)) = car_query.get_single()
+    else {
+        return;
+    };
+    let Some(wheels) = collect_wheels_telemetry(car, &wheels_query, &raycast_query) else {
+        return;
+    };
+
+    let now = time.elapsed_seconds();
+    overlay.history.push_back(OverlaySample {
+        time: now,
+        speed: car_transform.forward().dot(lin_vel) * 3.6,
+        wheel_rotation: car_physics.wheel_rotation,
+        wheels: wheels.map(|(_, telemetry)| telemetry),
+    });
+
+    while overlay.history.front().map_or(false, |s| now - s.time > HISTORY_DURATION) {
+        overlay.history.pop_front();
+    }
+}
+
+pub fn draw_telemetry_overlay(mut contexts: EguiContexts, overlay: Res<TelemetryOverlay>) {
+    if !overlay.visible {
+        return;
+    }
+
+    let history = &overlay.history;
+    egui::Window::new("Telemetry").resizable(false).show(contexts.ctx_mut(), |ui| {
+        plot(ui, "speed (km/h)", history, |s| [s.speed]);
+        plot(ui, "wheel rotation", history, |s| [s.wheel_rotation]);
+        plot(ui, "compression", history, |s| s.wheels.map(|w| w.compression));
+        plot(ui, "lateral force (N)", history, |s| s.wheels.map(|w| w.lateral_force));
+
+        ui.horizontal(|ui| {
+            for (i, wheel) in WHEELS.into_iter().enumerate() {
+                friction_circle(ui, wheel, WHEEL_COLORS[i], history, |s| s.wheels[i]);
+            }
+        });
+    });
+}
+
+/// Draws one line per value returned by `values`, auto-scaled on the visible history.
+fn plot<const N: usize>(
+    ui: &mut egui::Ui,
+    title: &str,
+    history: &VecDeque<OverlaySample>,
+    values: impl Fn(&OverlaySample) -> [f32; N],
+) {
+    let (response, painter) = ui.allocate_painter(GRAPH_SIZE, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+
+    let (min, max) = history
+        .iter()
+        .flat_map(&values)
+        .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), v| (min.min(v), max.max(v)));
+    let last = history.back();
+
+    if let Some(last) = last.filter(|_| min.is_finite() && max.is_finite()) {
+        // Avoid a division by zero when the value is constant
+        let (min, max) = if max - min < f32::EPSILON { (min - 1.0, max + 1.0) } else { (min, max) };
+        let to_screen = |time: f32, value: f32| {
+            let x = rect.right() - (last.time - time) / HISTORY_DURATION * rect.width();
+            let y = rect.bottom() - (value - min) / (max - min) * rect.height();
+            Pos2::new(x, y)
+        };
+
+        if min < 0.0 && max > 0.0 {
+            let y = to_screen(last.time, 0.0).y;
+            painter.hline(rect.x_range(), y, Stroke::new(1.0, Color32::DARK_GRAY));
+        }
+
+        for i in 0..N {
+            let color = if N == 1 { Color32::WHITE } else { WHEEL_COLORS[i % WHEEL_COLORS.len()] };
+            let points = history.iter().map(|s| to_screen(s.time, values(s)[i])).collect();
+            painter.add(Shape::line(points, Stroke::new(1.5, color)));
+        }
+
+        let current = values(last).map(|v| format!("{v:.1}")).join(" ");
+        painter.text(
+            rect.right_top() + egui::vec2(-4.0, 2.0),
+            Align2::RIGHT_TOP,
+            format!("{current} [{min:.1}, {max:.1}]"),
+            FontId::monospace(10.0),
+            Color32::LIGHT_GRAY,
+        );
+    }
+
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        Align2::LEFT_TOP,
+        title,
+        FontId::proportional(11.0),
+        Color32::WHITE,
+    );
+}
+
+/// Plots the forces applied by a tire relative to the load it carries.
+///
+/// The horizontal axis is the lateral force, the vertical one the acceleration force,
+/// both divided by the suspension spring force. The unit circle is drawn as a reference.
+fn friction_circle(
+    ui: &mut egui::Ui,
+    wheel: CarWheel,
+    color: Color32,
+    history: &VecDeque<OverlaySample>,
+    telemetry: impl Fn(&OverlaySample) -> WheelTelemetry,
+) {
+    let size = egui::Vec2::splat(FRICTION_CIRCLE_SIZE);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    let center = rect.center();
+    let radius = rect.width() * 0.3;
+
+    painter.rect_filled(rect, 2.0, Color32::from_black_alpha(160));
+    painter.circle_stroke(center, radius, Stroke::new(1.0, Color32::GRAY));
+    painter.text(
+        rect.left_top() + egui::vec2(4.0, 2.0),
+        Align2::LEFT_TOP,
+        wheel_label(wheel),
+        FontId::proportional(10.0),
+        Color32::WHITE,
+    );
+
+    let to_screen = |telemetry: WheelTelemetry| {
+        // A tire in the air doesn't transmit any force
+        (telemetry.spring_force > 0.0).then(|| {
+            let lateral = telemetry.lateral_force / telemetry.spring_force;
+            let longitudinal = telemetry.accel_force / telemetry.spring_force;
+            // Keep the point inside the widget, we want to see it even when sliding a lot
+            let offset = egui::vec2(lateral, -longitudinal)
+                .clamp(egui::Vec2::splat(-1.6), egui::Vec2::splat(1.6))
+                * radius;
+            center + offset
+        })
+    };
+
+    let Some(last) = history.back() else { return };
+    let trail: Vec<_> = history
+        .iter()
+        .filter(|s| last.time - s.time <= FRICTION_TRAIL_DURATION)
+        .filter_map(|s| to_screen(telemetry(s)))
+        .collect();
+    painter.add(Shape::line(trail, Stroke::new(1.0, color.gamma_multiply(0.5))));
+
+    if let Some(point) = to_screen(telemetry(last)) {
+        painter.circle_filled(point, 3.0, color);
+    }
+}
@@ -0,0 +1,354 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy_xpbd_3d::prelude::*;
+use serde::Deserialize;
+
+use crate::car_input::CarInput;
+use crate::car_suspension::CarPhysics;
+use crate::endless::RoadRng;
+use crate::map::SPAWN_HEIGHT;
+use crate::road_geometry::{RoadLanes, RoadSample};
+use crate::{spawn_car, MyAssets, PlayerCar};
+
+/// How far ahead of the player, along the road, the traffic appears.
+const SPAWN_AHEAD: Range<f32> = 120.0..350.0;
+
+/// The free space needed around a car appearing.
+const SPAWN_CLEARANCE: f32 = 15.0;
+
+/// How far behind the player, or away from it, the traffic disappears.
+const DESPAWN_BEHIND: f32 = 150.0;
+const DESPAWN_DISTANCE: f32 = 500.0;
+
+/// The speeds the traffic drives at on a free road, in m/s.
+const TARGET_SPEEDS: Range<f32> = 14.0..25.0;
+
+/// How far from its target speed a car coasts instead of accelerating or braking.
+const SPEED_TOLERANCE: f32 = 1.0;
+
+/// How far ahead a car steers toward, at least and per m/s.
+const LOOK_AHEAD: f32 = 8.0;
+const LOOK_AHEAD_TIME: f32 = 0.6;
+
+/// How much the wheels turn per radian between the car heading and its target.
+const STEERING_GAIN: f32 = 0.5;
+
+/// How far the wheels can be from their target rotation without being corrected.
+const STEERING_TOLERANCE: f32 = 0.02;
+
+/// The distance kept to the obstacle ahead, at least and per m/s.
+const MIN_GAP: f32 = 8.0;
+const FOLLOWING_TIME: f32 = 1.5;
+
+/// How far ahead the obstacles are seen.
+const SENSOR_RANGE: f32 = 60.0;
+
+/// An obstacle closer than this to a lane is in it.
+const CAR_HALF_WIDTH: f32 = 1.0;
+
+/// How much slower than its target speed the obstacle ahead must be to be overtaken.
+const OVERTAKE_SPEED_DIFFERENCE: f32 = 3.0;
+
+/// How long changing lanes takes, in seconds.
+const LANE_CHANGE_TIME: f32 = 2.5;
+
+/// How far from the middle of a road a body can be and still be on it.
+const ON_ROAD_DISTANCE: f32 = 20.0;
+
+/// How close the end of a road and the start of another are when one goes on with the other.
+const ROAD_JOIN_DISTANCE: f32 = 0.5;
+
+/// How many roads are followed looking ahead.
+const MAX_ROADS_AHEAD: usize = 8;
+
+/// The traffic of a map.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TrafficDefinition {
+    /// How many cars drive around the player.
+    pub cars: usize,
+    /// Seeds how far ahead the cars spawn, their lane and their speed.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// The traffic driving on the roads of the map.
+#[derive(Resource)]
+pub struct Traffic {
+    cars: usize,
+    rng: RoadRng,
+}
+
+impl Traffic {
+    pub fn new(definition: &TrafficDefinition) -> Traffic {
+        Traffic { cars: definition.cars, rng: RoadRng::new(definition.seed) }
+    }
+}
+
+/// A car driven along the lanes of the roads.
+#[derive(Component, Debug)]
+pub struct TrafficCar {
+    /// In m/s, when the road ahead is free.
+    pub target_speed: f32,
+    /// The lane it drives in, counted from the left.
+    pub lane: u32,
+    /// Where it drives from the middle of the road, moving to its lane when changing lanes.
+    offset: f32,
+}
+
+/// Where a position is on the nearest road.
+struct OnRoad<'a> {
+    lanes: &'a RoadLanes,
+    road_transform: Transform,
+    index: usize,
+    /// From the middle of the road, positive to the right.
+    offset: f32,
+}
+
+impl OnRoad<'_> {
+    /// The nearest cross-section, in world space.
+    fn sample(&self) -> RoadSample {
+        let sample = &self.lanes.samples[self.index];
+        RoadSample {
+            position: self.road_transform.transform_point(sample.position),
+            forward: self.road_transform.rotation * sample.forward,
+            ..*sample
+        }
+    }
+}
+
+fn find_on_road<'a>(
+    roads_q: &'a Query<(&RoadLanes, &Transform)>,
+    position: Vec3,
+) -> Option<OnRoad<'a>> {
+    roads_q
+        .iter()
+        .filter_map(|(lanes, road_transform)| {
+            let local = road_transform.compute_affine().inverse().transform_point3(position);
+            let index = lanes.nearest(local)?;
+            let relative = local - lanes.samples[index].position;
+            let offset = relative.dot(lanes.samples[index].right());
+            let on_road = OnRoad { lanes, road_transform: *road_transform, index, offset };
+            Some((relative.length_squared(), on_road))
+        })
+        .filter(|(distance_squared, _)| *distance_squared < ON_ROAD_DISTANCE * ON_ROAD_DISTANCE)
+        .min_by(|a, b| a.0.total_cmp(&b.0))
+        .map(|(_, on_road)| on_road)
+}
+
+/// About `ahead` meters further along the road, going on with the road starting where an
+/// open one ends, and how much of that distance is left when the roads end before.
+fn road_ahead<'a>(
+    roads_q: &'a Query<(&RoadLanes, &Transform)>,
+    on_road: &OnRoad<'a>,
+    ahead: f32,
+) -> (OnRoad<'a>, f32) {
+    let (mut lanes, mut road_transform, mut index) =
+        (on_road.lanes, on_road.road_transform, on_road.index);
+    let mut remaining = ahead;
+
+    for _ in 0..MAX_ROADS_AHEAD {
+        let (next, travelled) = lanes.index_ahead(index, remaining);
+        index = next;
+        remaining -= travelled;
+        if remaining <= 0.0 || lanes.closed {
+            break;
+        }
+
+        let end = road_transform.transform_point(lanes.samples[index].position);
+        let joined = roads_q.iter().find(|(other, other_transform)| {
+            other.samples.first().map_or(false, |first| {
+                other_transform.transform_point(first.position).distance(end) < ROAD_JOIN_DISTANCE
+            })
+        });
+        let Some((other, &other_transform)) = joined else { break };
+        (lanes, road_transform, index) = (other, other_transform, 0);
+    }
+
+    (OnRoad { lanes, road_transform, index, offset: 0.0 }, remaining.max(0.0))
+}
+
+/// Whether to steer left or right to turn the wheels toward a target, more the further
+/// it is from the heading of the car.
+pub fn steer_toward(transform: &Transform, car_physics: &CarPhysics, target: Vec3) -> (bool, bool) {
+    let local = transform.rotation.inverse() * (target - transform.translation);
+    // Positive to the left, where the wheels turn with a lower rotation
+    let heading_error = (-local.x).atan2(-local.z);
+    let wheel_rotation = (0.5 - heading_error * STEERING_GAIN).clamp(0.2, 0.8);
+    (
+        car_physics.wheel_rotation > wheel_rotation + STEERING_TOLERANCE,
+        car_physics.wheel_rotation < wheel_rotation - STEERING_TOLERANCE,
+    )
+}
+
+/// Where another body is from a traffic car, along the road.
+struct Nearby {
+    gap: f32,
+    speed: f32,
+    /// From the middle of the road.
+    offset: f32,
+}
+
+/// Drives the traffic along its lanes with the same inputs as the player, slowing down
+/// behind the obstacles and changing lanes to overtake them.
+pub fn drive_traffic(
+    time: Res<Time>,
+    mut traffic_q: Query<(
+        Entity,
+        &mut TrafficCar,
+        &mut CarInput,
+        &CarPhysics,
+        &Transform,
+        &LinearVelocity,
+    )>,
+    bodies_q: Query<(Entity, &RigidBody, &Transform, &LinearVelocity)>,
+    roads_q: Query<(&RoadLanes, &Transform)>,
+) {
+    // Every dynamic body on a road is in the way, the player and the other cars included
+    let obstacles: Vec<_> = bodies_q
+        .iter()
+        .filter(|(_, body, _, _)| matches!(body, RigidBody::Dynamic))
+        .filter_map(|(entity, _, transform, &LinearVelocity(velocity))| {
+            let on_road = find_on_road(&roads_q, transform.translation)?;
+            Some((entity, transform.translation, velocity, on_road.offset))
+        })
+        .collect();
+
+    for (entity, mut car, mut input, car_physics, transform, &LinearVelocity(lin_vel)) in
+        &mut traffic_q
+    {
+        let Some(on_road) = find_on_road(&roads_q, transform.translation) else {
+            // Off the roads, let it roll until it is despawned
+            input.set_if_neq(CarInput::default());
+            continue;
+        };
+        let sample = on_road.sample();
+        let lanes = on_road.lanes.lanes;
+        let lane_width = sample.width / lanes as f32;
+        let speed = transform.forward().dot(lin_vel);
+        let safe_gap = MIN_GAP + speed.max(0.0) * FOLLOWING_TIME;
+
+        let nearby: Vec<_> = obstacles
+            .iter()
+            .filter(|&&(other, ..)| other != entity)
+            .map(|&(_, position, velocity, offset)| Nearby {
+                gap: (position - transform.translation).dot(sample.forward),
+                speed: velocity.dot(sample.forward),
+                offset,
+            })
+            .collect();
+        let in_lane = |lane: u32, other: &Nearby| {
+            (other.offset - sample.lane_offset(lanes, lane)).abs()
+                < lane_width / 2.0 + CAR_HALF_WIDTH
+        };
+
+        // The road can narrow under the car
+        car.lane = car.lane.min(lanes - 1);
+
+        let ahead = nearby
+            .iter()
+            .filter(|other| in_lane(car.lane, other) && other.gap > 0.0 && other.gap < SENSOR_RANGE)
+            .min_by(|a, b| a.gap.total_cmp(&b.gap));
+
+        let mut target_speed = car.target_speed;
+        if let Some(ahead) = ahead {
+            // Slow down to the speed of the obstacle, more the closer it is
+            let following_speed = ahead.speed + (ahead.gap - safe_gap) / FOLLOWING_TIME;
+            target_speed = target_speed.min(following_speed.max(0.0));
+
+            let changing_lanes = (car.offset - sample.lane_offset(lanes, car.lane)).abs() > 0.1;
+            let blocked = ahead.gap < safe_gap * 2.0
+                && ahead.speed < car.target_speed - OVERTAKE_SPEED_DIFFERENCE;
+            if blocked && !changing_lanes {
+                let free = |lane: u32| {
+                    !nearby.iter().any(|other| {
+                        in_lane(lane, other)
+                            && other.gap > -MIN_GAP
+                            && other.gap < safe_gap + MIN_GAP
+                    })
+                };
+                // Overtake on the left when it is free
+                let candidates =
+                    [car.lane.checked_sub(1), Some(car.lane + 1).filter(|&l| l < lanes)];
+                if let Some(lane) = candidates.into_iter().flatten().find(|&lane| free(lane)) {
+                    car.lane = lane;
+                }
+            }
+        }
+
+        let lane_offset = sample.lane_offset(lanes, car.lane);
+        let max_step = lane_width / LANE_CHANGE_TIME * time.delta_seconds();
+        car.offset += (lane_offset - car.offset).clamp(-max_step, max_step);
+
+        let look_ahead = LOOK_AHEAD + speed.max(0.0) * LOOK_AHEAD_TIME;
+        let target_sample = road_ahead(&roads_q, &on_road, look_ahead).0.sample();
+        let target = target_sample.position + target_sample.right() * car.offset;
+        let (steer_left, steer_right) = steer_toward(transform, car_physics, target);
+
+        input.set_if_neq(CarInput {
+            accelerate: speed < target_speed - SPEED_TOLERANCE,
+            reverse: speed > target_speed + SPEED_TOLERANCE,
+            steer_left,
+            steer_right,
+            ..default()
+        });
+    }
+}
+
+/// Adds the missing traffic on a free lane ahead of the player, one car at a time.
+pub fn spawn_traffic(
+    mut commands: Commands,
+    assets: Res<MyAssets>,
+    mut traffic: ResMut<Traffic>,
+    player_q: Query<&Transform, With<PlayerCar>>,
+    cars_q: Query<&Transform, With<CarPhysics>>,
+    traffic_q: Query<(), With<TrafficCar>>,
+    roads_q: Query<(&RoadLanes, &Transform)>,
+) {
+    if traffic_q.iter().count() >= traffic.cars {
+        return;
+    }
+    let Ok(player_transform) = player_q.get_single() else { return };
+    let Some(on_road) = find_on_road(&roads_q, player_transform.translation) else { return };
+
+    let mut draw = |range: Range<f32>| range.start + traffic.rng.unit() * (range.end - range.start);
+    let (ahead, lane_draw, target_speed) = (draw(SPAWN_AHEAD), draw(0.0..1.0), draw(TARGET_SPEEDS));
+
+    let (spawn_at, remaining) = road_ahead(&roads_q, &on_road, ahead);
+    // The road doesn't go that far, yet
+    if remaining > 0.0 {
+        return;
+    }
+    let sample = spawn_at.sample();
+    let lanes = spawn_at.lanes.lanes;
+    let lane = ((lane_draw * lanes as f32) as u32).min(lanes - 1);
+    let offset = sample.lane_offset(lanes, lane);
+    let position = sample.position + sample.right() * offset + sample.up() * SPAWN_HEIGHT;
+    if cars_q.iter().any(|car| car.translation.distance(position) < SPAWN_CLEARANCE) {
+        return;
+    }
+
+    let transform = Transform::from_translation(position).looking_to(sample.forward, sample.up());
+    let car = spawn_car(&mut commands, &assets.porsche, transform);
+    commands.entity(car).insert((
+        TrafficCar { target_speed, lane, offset },
+        LinearVelocity(sample.forward * target_speed),
+    ));
+}
+
+/// Removes the traffic left behind by the player.
+pub fn despawn_traffic(
+    mut commands: Commands,
+    player_q: Query<&Transform, With<PlayerCar>>,
+    traffic_q: Query<(Entity, &Transform), With<TrafficCar>>,
+) {
+    let Ok(player_transform) = player_q.get_single() else { return };
+    for (car, transform) in &traffic_q {
+        let relative = transform.translation - player_transform.translation;
+        if relative.dot(player_transform.forward()) < -DESPAWN_BEHIND
+            || relative.length() > DESPAWN_DISTANCE
+        {
+            commands.entity(car).despawn_recursive();
+        }
+    }
+}
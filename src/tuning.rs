@@ -0,0 +1,342 @@
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use bevy::reflect::{ReflectRef, TypeRegistry};
+use bevy::scene::serde::SceneDeserializer;
+use bevy::window::PrimaryWindow;
+use bevy_inspector_egui::bevy_egui::EguiContext;
+use bevy_inspector_egui::egui;
+use bevy_xpbd_3d::prelude::*;
+use serde::de::DeserializeSeed;
+
+use crate::car_suspension::CarPhysics;
+use crate::save::SaveFile;
+use crate::PlayerCar;
+
+/// The directory, relative to the working directory, where presets are stored.
+const PRESETS_DIRECTORY: &str = "presets";
+
+/// The tuning presets panel, toggled with F5.
+#[derive(Resource, Default)]
+pub struct TuningPanel {
+    visible: bool,
+    name: String,
+    presets: Vec<String>,
+    diff_left: Option<String>,
+    diff_right: Option<String>,
+    differences: Vec<PresetDifference>,
+    message: Option<String>,
+}
+
+/// The tuned components of a car, as saved in a preset file.
+pub struct TuningPreset {
+    components: Vec<Box<dyn Reflect>>,
+}
+
+/// A field whose value is not the same in two presets.
+pub struct PresetDifference {
+    pub field: String,
+    pub left: String,
+    pub right: String,
+}
+
+enum TuningAction {
+    Refresh,
+    Save(String),
+    Load(String),
+    Diff(String, String),
+}
+
+impl TuningPreset {
+    /// Extracts the tuned components of the given car using reflection.
+    pub fn extract(world: &World, car: Entity) -> TuningPreset {
+        let scene = DynamicSceneBuilder::from_world(world)
+            .deny_all()
+            .allow::<CarPhysics>()
+            .allow::<Mass>()
+            .allow::<AngularDamping>()
+            .allow::<LinearDamping>()
+            .extract_entity(car)
+            .build();
+        let components = scene.entities.into_iter().flat_map(|e| e.components).collect();
+        TuningPreset { components }
+    }
+
+    /// Overwrites the components of the car with the ones of this preset.
+    pub fn apply(&self, world: &mut World, car: Entity) -> Result<(), Box<dyn Error>> {
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        let mut entity = world.entity_mut(car);
+
+        for component in &self.components {
+            let type_path = component_type_path(component.as_ref());
+            let reflect_component = registry
+                .get_with_type_path(type_path)
+                .and_then(|registration| registration.data::<ReflectComponent>())
+                .ok_or_else(|| format!("{type_path} is not a registered component"))?;
+            reflect_component.apply(&mut entity, component.as_ref());
+        }
+
+        Ok(())
+    }
+
+    pub fn load(path: &Path, registry: &TypeRegistry) -> Result<TuningPreset, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut deserializer = ron::de::Deserializer::from_str(&text)?;
+        let scene = SceneDeserializer { type_registry: registry }.deserialize(&mut deserializer)?;
+        let components = scene.entities.into_iter().flat_map(|e| e.components).collect();
+        Ok(TuningPreset { components })
+    }
+
+    pub fn save(&self, path: &Path, registry: &AppTypeRegistry) -> Result<(), Box<dyn Error>> {
+        // We save the preset as a single entity scene to benefit from the scene serializer
+        let scene = DynamicScene {
+            resources: Vec::new(),
+            entities: vec![DynamicEntity {
+                entity: Entity::PLACEHOLDER,
+                components: self.components.iter().map(|c| c.clone_value()).collect(),
+            }],
+        };
+        let text = scene.serialize_ron(registry)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Lists the fields that differ between the two presets.
+    pub fn diff(&self, other: &TuningPreset) -> Vec<PresetDifference> {
+        let mut differences = Vec::new();
+
+        for left in &self.components {
+            let type_path = component_type_path(left.as_ref());
+            let right =
+                other.components.iter().find(|c| component_type_path(c.as_ref()) == type_path);
+            let name = short_type_name(type_path).to_string();
+            match right {
+                Some(right) => diff_values(name, left.as_ref(), right.as_ref(), &mut differences),
+                None => differences.push(PresetDifference {
+                    field: name,
+                    left: format!("{left:?}"),
+                    right: "missing".to_string(),
+                }),
+            }
+        }
+
+        for right in &other.components {
+            let type_path = component_type_path(right.as_ref());
+            if !self.components.iter().any(|c| component_type_path(c.as_ref()) == type_path) {
+                differences.push(PresetDifference {
+                    field: short_type_name(type_path).to_string(),
+                    left: "missing".to_string(),
+                    right: format!("{right:?}"),
+                });
+            }
+        }
+
+        differences
+    }
+}
+
+/// The type path of the component, even when it is a dynamic representation.
+fn component_type_path(component: &dyn Reflect) -> &str {
+    component
+        .get_represented_type_info()
+        .map_or_else(|| component.reflect_type_path(), |info| info.type_path())
+}
+
+fn short_type_name(type_path: &str) -> &str {
+    type_path.rsplit("::").next().unwrap_or(type_path)
+}
+
+fn diff_values(
+    field: String,
+    left: &dyn Reflect,
+    right: &dyn Reflect,
+    differences: &mut Vec<PresetDifference>,
+) {
+    match (left.reflect_ref(), right.reflect_ref()) {
+        (ReflectRef::Struct(l), ReflectRef::Struct(r)) => {
+            for (i, value) in l.iter_fields().enumerate() {
+                let name = l.name_at(i).unwrap();
+                match r.field(name) {
+                    Some(other) => {
+                        diff_values(format!("{field}.{name}"), value, other, differences)
+                    }
+                    None => differences.push(PresetDifference {
+                        field: format!("{field}.{name}"),
+                        left: format!("{value:?}"),
+                        right: "missing".to_string(),
+                    }),
+                }
+            }
+        }
+        (ReflectRef::TupleStruct(l), ReflectRef::TupleStruct(r))
+            if l.field_len() == r.field_len() =>
+        {
+            for (i, (value, other)) in l.iter_fields().zip(r.iter_fields()).enumerate() {
+                diff_values(format!("{field}.{i}"), value, other, differences);
+            }
+        }
+        _ => {
+            if left.reflect_partial_eq(right) != Some(true) {
+                differences.push(PresetDifference {
+                    field,
+                    left: format!("{left:?}"),
+                    right: format!("{right:?}"),
+                });
+            }
+        }
+    }
+}
+
+pub fn preset_path(name: &str) -> PathBuf {
+    // Not `with_extension`, which would cut the names with a dot
+    Path::new(PRESETS_DIRECTORY).join(format!("{name}.ron"))
+}
+
+pub fn list_presets() -> Vec<String> {
+    let mut presets: Vec<_> = fs::read_dir(PRESETS_DIRECTORY)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+        .filter_map(|path| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+        .collect();
+    presets.sort_unstable();
+    presets
+}
+
+fn run_action(
+    world: &mut World,
+    panel: &mut TuningPanel,
+    action: TuningAction,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let registry = world.resource::<AppTypeRegistry>().clone();
+    let car = world.query_filtered::<Entity, With<PlayerCar>>().get_single(world);
+
+    match action {
+        TuningAction::Refresh => {
+            panel.presets = list_presets();
+            Ok(None)
+        }
+        TuningAction::Save(name) => {
+            let car = car?;
+            let path = preset_path(&name);
+            TuningPreset::extract(world, car).save(&path, &registry)?;
+            panel.presets = list_presets();
+            // The cars tuned by the player don't have to be unlocked
+            if let Some(mut save) = world.get_resource_mut::<SaveFile>() {
+                if save.data.unlocked_cars.insert(name) {
+                    save.store();
+                }
+            }
+            Ok(Some(format!("saved {}", path.display())))
+        }
+        TuningAction::Load(name) => {
+            let car = car?;
+            if let Some(save) = world.get_resource::<SaveFile>() {
+                if !save.data.unlocked_cars.contains(&name) {
+                    return Err(format!("{name} is locked").into());
+                }
+            }
+            let path = preset_path(&name);
+            let preset = TuningPreset::load(&path, &registry.read())?;
+            preset.apply(world, car)?;
+            Ok(Some(format!("loaded {}", path.display())))
+        }
+        TuningAction::Diff(left, right) => {
+            let registry = registry.read();
+            let left = TuningPreset::load(&preset_path(&left), &registry)?;
+            let right = TuningPreset::load(&preset_path(&right), &registry)?;
+            panel.differences = left.diff(&right);
+            Ok(Some(format!("{} differences", panel.differences.len())))
+        }
+    }
+}
+
+pub fn toggle_tuning_panel(keys: Res<Input<KeyCode>>, mut panel: ResMut<TuningPanel>) {
+    if keys.just_pressed(KeyCode::F5) {
+        panel.visible = !panel.visible;
+        if panel.visible {
+            panel.presets = list_presets();
+        }
+    }
+}
+
+pub fn tuning_panel(world: &mut World) {
+    if !world.resource::<TuningPanel>().visible {
+        return;
+    }
+
+    let mut egui_context = {
+        let mut query = world.query_filtered::<&EguiContext, With<PrimaryWindow>>();
+        let Ok(egui_context) = query.get_single(world) else { return };
+        egui_context.clone()
+    };
+
+    world.resource_scope(|world, mut panel: Mut<TuningPanel>| {
+        let mut action = None;
+
+        egui::Window::new("Tuning presets").show(egui_context.get_mut(), |ui| {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut panel.name);
+                let valid_name = !panel.name.trim().is_empty() && !panel.name.contains(['/', '\\']);
+                if ui.add_enabled(valid_name, egui::Button::new("Save")).clicked() {
+                    action = Some(TuningAction::Save(panel.name.trim().to_string()));
+                }
+                if ui.button("Refresh").clicked() {
+                    action = Some(TuningAction::Refresh);
+                }
+            });
+
+            ui.separator();
+            egui::Grid::new("presets").striped(true).show(ui, |ui| {
+                let TuningPanel { presets, diff_left, diff_right, .. } = &mut *panel;
+                for preset in presets.iter() {
+                    ui.label(preset);
+                    if ui.button("Load").clicked() {
+                        action = Some(TuningAction::Load(preset.clone()));
+                    }
+                    ui.radio_value(diff_left, Some(preset.clone()), "A");
+                    ui.radio_value(diff_right, Some(preset.clone()), "B");
+                    ui.end_row();
+                }
+            });
+
+            if let (Some(left), Some(right)) = (&panel.diff_left, &panel.diff_right) {
+                if ui.button("Diff A and B").clicked() {
+                    action = Some(TuningAction::Diff(left.clone(), right.clone()));
+                }
+            }
+
+            if !panel.differences.is_empty() {
+                ui.separator();
+                egui::Grid::new("differences").striped(true).show(ui, |ui| {
+                    for PresetDifference { field, left, right } in &panel.differences {
+                        ui.label(field);
+                        ui.label(left);
+                        ui.label(right);
+                        ui.end_row();
+                    }
+                });
+            }
+
+            if let Some(message) = &panel.message {
+                ui.separator();
+                ui.label(message);
+            }
+        });
+
+        if let Some(action) = action {
+            match run_action(world, &mut panel, action) {
+                Ok(Some(message)) => panel.message = Some(message),
+                Ok(None) => (),
+                Err(e) => panel.message = Some(format!("error: {e}")),
+            }
+        }
+    });
+}